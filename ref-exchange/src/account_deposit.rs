@@ -11,7 +11,9 @@ use near_sdk::{
     AccountId, Balance, PromiseResult, StorageUsage,
 };
 use crate::legacy::AccountV1;
-use crate::utils::{ext_self, GAS_FOR_FT_TRANSFER, GAS_FOR_RESOLVE_TRANSFER};
+use crate::utils::{
+    ext_self, GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER, GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER,
+};
 use crate::*;
 
 // [AUDIT_01]
@@ -36,6 +38,12 @@ const ACC_ID_AS_CLT_KEY_STORAGE: StorageUsage = ACC_ID_AS_KEY_STORAGE + 1;
 // + U32_STORAGE: legacy_tokens HashMap length
 // + U32_STORAGE: tokens HashMap length
 // + U64_STORAGE: storage_used
+/// Max number of non-whitelisted tokens kept in lost-found of an account.
+pub const MAX_LOSTFOUND_UNLISTED_TOKENS: usize = 10;
+
+/// Max number of tokens in one `withdraw_batch`, so the transfers and the callback fit in a transaction's gas.
+pub const MAX_WITHDRAW_BATCH: usize = 5;
+
 pub const INIT_ACCOUNT_STORAGE: StorageUsage =
     ACC_ID_AS_CLT_KEY_STORAGE + 1 + U128_STORAGE + U32_STORAGE + U32_STORAGE + U64_STORAGE;
 
@@ -167,6 +175,13 @@ impl Account {
             * env::storage_byte_cost()
     }
 
+    /// Returns amount of $NEAR necessary to cover storage after given number of tokens get registered.
+    pub(crate) fn storage_usage_with_new_tokens(&self, num_tokens: u64) -> Balance {
+        self.storage_usage()
+            + (num_tokens * (KEY_PREFIX_ACC + ACC_ID_AS_KEY_STORAGE + U128_STORAGE)) as Balance
+                * env::storage_byte_cost()
+    }
+
     /// Returns how much NEAR is available for storage.
     pub fn storage_available(&self) -> Balance {
         // [AUDIT_01] avoid math overflow
@@ -202,6 +217,26 @@ impl Account {
         }
     }
 
+    /// Moves the balance of `old_token_id` to `new_token_id`, returns it if the old token was registered.
+    /// The new token takes the old one's entry unless already registered,
+    /// so `storage_usage` never grows and the account needs no more storage deposit.
+    pub(crate) fn migrate_token(&mut self, old_token_id: &AccountId, new_token_id: &AccountId) -> Option<Balance> {
+        let new_registered = self.get_balance(new_token_id).is_some();
+        if let Some(balance) = self.legacy_tokens.remove(old_token_id) {
+            if new_registered {
+                self.deposit(new_token_id, balance);
+            } else {
+                self.legacy_tokens.insert(new_token_id.clone(), balance);
+            }
+            Some(balance)
+        } else if let Some(balance) = self.tokens.remove(old_token_id) {
+            self.deposit(new_token_id, balance);
+            Some(balance)
+        } else {
+            None
+        }
+    }
+
     /// Unregisters `token_id` from this account balance.
     /// Panics if the `token_id` balance is not 0.
     pub(crate) fn unregister(&mut self, token_id: &AccountId) {
@@ -212,6 +247,17 @@ impl Account {
     }
 }
 
+/// Outcome of one token transfer in `withdraw_batch`.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct WithdrawResult {
+    pub token_id: AccountId,
+    pub amount: U128,
+    /// When false, the amount was credited back.
+    pub success: bool,
+}
+
 #[near_bindgen]
 impl Contract {
     /// Registers given token in the user's account deposit.
@@ -244,16 +290,29 @@ impl Contract {
     /// a zero amount means to withdraw all in user's inner account.
     /// Optional unregister will try to remove record of this token from AccountDeposit for given user.
     /// Unregister will fail if the left over balance is non 0.
+    /// Optional receiver_id gets the tokens instead of the caller,
+    /// if the transfer fails (e.g. receiver not registered on the token) tokens return to caller's balance.
+    /// With msg given, tokens are sent by `ft_transfer_call` and the unused part returns to caller's balance,
+    /// the prepaid gas must cover the call and its resolution.
     #[payable]
     pub fn withdraw(
         &mut self,
         token_id: ValidAccountId,
         amount: U128,
         unregister: Option<bool>,
+        receiver_id: Option<ValidAccountId>,
+        msg: Option<String>,
     ) -> Promise {
         assert_one_yocto();
         self.assert_contract_running();
         let token_id: AccountId = token_id.into();
+        if msg.is_some() {
+            assert!(
+                env::prepaid_gas() >= GAS_FOR_FT_TRANSFER_CALL + GAS_FOR_BASIC_OP,
+                "{}",
+                ERR44_TRANSFER_CALL_GAS
+            );
+        }
         let sender_id = env::predecessor_account_id();
         let mut account = self.internal_unwrap_account(&sender_id);
         
@@ -270,7 +329,11 @@ impl Contract {
             account.unregister(&token_id);
         }
         self.internal_save_account(&sender_id, account);
-        self.internal_send_tokens(&sender_id, &token_id, amount)
+        let receiver_id = receiver_id.map(|r| r.into()).unwrap_or_else(|| sender_id.clone());
+        match msg {
+            Some(msg) => self.internal_send_tokens_call(&sender_id, &receiver_id, &token_id, amount, msg),
+            None => self.internal_send_tokens_to(&sender_id, &receiver_id, &token_id, amount),
+        }
     }
 
     #[private]
@@ -286,49 +349,252 @@ impl Contract {
             "{}",
             ERR25_CALLBACK_POST_WITHDRAW_INVALID
         );
-        match env::promise_result(0) {
+        self.internal_resolve_withdraw(0, &token_id, &sender_id, amount);
+    }
+
+    /// Claims tokens kept for the caller in lost-found into caller's inner account.
+    /// Caller must be registered with enough storage, works for non-whitelisted tokens as well.
+    #[payable]
+    pub fn claim_lostfound(&mut self, token_id: ValidAccountId) -> U128 {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let token_id: AccountId = token_id.into();
+        let sender_id = env::predecessor_account_id();
+        let mut tokens = self.lostfound.get(&sender_id).unwrap_or_default();
+        let amount = tokens.remove(&token_id).expect(ERR30_NO_LOSTFOUND);
+        if tokens.is_empty() {
+            self.lostfound.remove(&sender_id);
+        } else {
+            self.lostfound.insert(&sender_id, &tokens);
+        }
+        let mut account = self.internal_unwrap_account(&sender_id);
+        account.deposit(&token_id, amount);
+        self.internal_save_account(&sender_id, account);
+        Event::LostfoundClaimed {
+            account_id: &sender_id,
+            token_id: &token_id,
+            amount: U128(amount),
+        }
+        .emit();
+        U128(amount)
+    }
+
+    /// Resolves withdraw sent by `ft_transfer_call`, returning the unused amount to sender's balance.
+    #[private]
+    pub fn exchange_callback_post_withdraw_call(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        let used_amount = match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
-            PromiseResult::Successful(_) => {}
-            PromiseResult::Failed => {
-                // This reverts the changes from withdraw function.
-                // If account doesn't exit, deposits to the owner's account as lostfound.
-                let mut failed = false;
-                if let Some(mut account) = self.internal_get_account(&sender_id) {
-                    if account.deposit_with_storage_check(&token_id, amount.0) {
-                        // cause storage already checked, here can directly save
-                        self.accounts.insert(&sender_id, &account.into());
-                    } else {
-                        // we can ensure that internal_get_account here would NOT cause a version upgrade, 
-                        // cause it is callback, the account must be the current version or non-exist,
-                        // so, here we can just leave it without insert, won't cause storage collection inconsistency.
-                        env::log(
-                            format!(
-                                "Account {} has not enough storage. Depositing to owner.",
-                                sender_id
-                            )
-                            .as_bytes(),
-                        );
-                        failed = true;
-                    }
+            PromiseResult::Successful(value) => {
+                if let Ok(used_amount) = near_sdk::serde_json::from_slice::<U128>(&value) {
+                    std::cmp::min(amount.0, used_amount.0)
                 } else {
-                    env::log(
-                        format!(
-                            "Account {} is not registered. Depositing to owner.",
-                            sender_id
-                        )
-                        .as_bytes(),
-                    );
-                    failed = true;
-                }
-                if failed {
-                    self.internal_lostfound(&token_id, amount.0);
+                    amount.0
                 }
             }
+            PromiseResult::Failed => 0,
         };
+        if used_amount > 0 {
+            self.internal_decrease_liability_saturating(&token_id, used_amount);
+            Event::Withdraw {
+                account_id: &sender_id,
+                token_id: &token_id,
+                amount: U128(used_amount),
+                success: true,
+            }
+            .emit();
+        }
+        if used_amount < amount.0 {
+            let unused_amount = U128(amount.0 - used_amount);
+            self.internal_refund_withdraw(&token_id, &sender_id, unused_amount.0);
+            Event::Withdraw {
+                account_id: &sender_id,
+                token_id: &token_id,
+                amount: unused_amount,
+                success: false,
+            }
+            .emit();
+        }
+    }
+
+    /// Withdraws given tokens from the deposits of given user, all transfers are sent together.
+    /// Same as `withdraw` for each token: a zero amount means to withdraw all,
+    /// and unregister fails if the left over balance of any token is non 0.
+    /// Each transfer succeeds or fails on its own, the callback returns result of each token.
+    /// At most `MAX_WITHDRAW_BATCH` tokens, and the prepaid gas must cover a transfer
+    /// and its resolution per token.
+    #[payable]
+    pub fn withdraw_batch(
+        &mut self,
+        token_amounts: Vec<(ValidAccountId, U128)>,
+        unregister: Option<bool>,
+    ) -> Promise {
+        assert_one_yocto();
+        self.assert_contract_running();
+        assert!(!token_amounts.is_empty(), "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+        assert!(token_amounts.len() <= MAX_WITHDRAW_BATCH, "{}", ERR42_WITHDRAW_BATCH_TOO_LARGE);
+        let callback_gas = GAS_FOR_RESOLVE_TRANSFER * token_amounts.len() as u64;
+        assert!(
+            env::prepaid_gas()
+                >= GAS_FOR_FT_TRANSFER * token_amounts.len() as u64 + callback_gas + GAS_FOR_BASIC_OP,
+            "{}",
+            ERR43_WITHDRAW_BATCH_GAS
+        );
+        check_token_duplicates(
+            &token_amounts.iter().map(|(token_id, _)| token_id.clone()).collect::<Vec<_>>(),
+        );
+        let sender_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&sender_id);
+
+        let mut token_ids = vec![];
+        let mut amounts = vec![];
+        for (token_id, amount) in token_amounts {
+            let token_id: AccountId = token_id.into();
+            let mut amount: u128 = amount.into();
+            if amount == 0 {
+                amount = account.get_balance(&token_id).expect(ERR21_TOKEN_NOT_REG);
+            }
+            assert!(amount > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+            account.withdraw(&token_id, amount);
+            if unregister == Some(true) {
+                account.unregister(&token_id);
+            }
+            token_ids.push(token_id);
+            amounts.push(U128(amount));
+        }
+        self.internal_save_account(&sender_id, account);
+
+        let mut transfers = token_ids.iter().zip(amounts.iter()).map(|(token_id, amount)| {
+            ext_fungible_token::ft_transfer(
+                sender_id.clone(),
+                *amount,
+                None,
+                token_id,
+                1,
+                GAS_FOR_FT_TRANSFER,
+            )
+        });
+        let first = transfers.next().unwrap();
+        transfers
+            .fold(first, |all, transfer| all.and(transfer))
+            .then(ext_self::exchange_callback_post_withdraw_batch(
+                token_ids,
+                sender_id,
+                amounts,
+                &env::current_account_id(),
+                0,
+                callback_gas,
+            ))
+    }
+
+    #[private]
+    pub fn exchange_callback_post_withdraw_batch(
+        &mut self,
+        token_ids: Vec<AccountId>,
+        sender_id: AccountId,
+        amounts: Vec<U128>,
+    ) -> Vec<WithdrawResult> {
+        assert_eq!(
+            env::promise_results_count(),
+            token_ids.len() as u64,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        token_ids
+            .into_iter()
+            .zip(amounts)
+            .enumerate()
+            .map(|(index, (token_id, amount))| {
+                let success =
+                    self.internal_resolve_withdraw(index as u64, &token_id, &sender_id, amount);
+                WithdrawResult {
+                    token_id,
+                    amount,
+                    success,
+                }
+            })
+            .collect()
     }
 }
 
 impl Contract {
+    /// Handles result of the transfer at `result_index` sending `amount` of token to `sender_id`.
+    /// If it failed, returns the amount back to sender's balance, or to lost-found if it can't.
+    /// Returns whether the transfer succeeded.
+    fn internal_resolve_withdraw(
+        &mut self,
+        result_index: u64,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: U128,
+    ) -> bool {
+        let success = match env::promise_result(result_index) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                self.internal_decrease_liability_saturating(token_id, amount.0);
+                true
+            }
+            PromiseResult::Failed => {
+                self.internal_refund_withdraw(token_id, sender_id, amount.0);
+                false
+            }
+        };
+        Event::Withdraw {
+            account_id: sender_id,
+            token_id,
+            amount,
+            success,
+        }
+        .emit();
+        success
+    }
+
+    /// Returns amount of a failed withdraw back to sender's balance.
+    /// If account doesn't exist or lacks storage, keeps it in lost-found for the sender to claim.
+    fn internal_refund_withdraw(&mut self, token_id: &AccountId, sender_id: &AccountId, amount: Balance) {
+        // This reverts the changes from withdraw function.
+        let mut failed = false;
+        if let Some(mut account) = self.internal_get_account(sender_id) {
+            if account.deposit_with_storage_check(token_id, amount) {
+                // cause storage already checked, here can directly save
+                self.accounts.insert(sender_id, &account.into());
+            } else {
+                // we can ensure that internal_get_account here would NOT cause a version upgrade, 
+                // cause it is callback, the account must be the current version or non-exist,
+                // so, here we can just leave it without insert, won't cause storage collection inconsistency.
+                env::log(
+                    format!(
+                        "Account {} has not enough storage. Depositing to lostfound.",
+                        sender_id
+                    )
+                    .as_bytes(),
+                );
+                failed = true;
+            }
+        } else {
+            env::log(
+                format!(
+                    "Account {} is not registered. Depositing to lostfound.",
+                    sender_id
+                )
+                .as_bytes(),
+            );
+            failed = true;
+        }
+        if failed {
+            self.internal_lostfound(sender_id, token_id, amount);
+        }
+    }
 
     /// Checks that account has enough storage to be stored and saves it into collection.
     /// This should be only place to directly use `self.accounts`.
@@ -337,17 +603,42 @@ impl Contract {
         self.accounts.insert(&account_id, &account.into());
     }
 
-    /// save token to owner account as lostfound, no need to care about storage
-    /// only global whitelisted token can be stored in lost-found
-    pub(crate) fn internal_lostfound(&mut self, token_id: &AccountId, amount: u128) {
-        if self.whitelisted_tokens.contains(token_id) {
-            let mut lostfound = self.internal_unwrap_or_default_account(&self.owner_id);
-            lostfound.deposit(token_id, amount);
-            self.accounts.insert(&self.owner_id, &lostfound.into());
-        } else {
-            env::panic("ERR: non-whitelisted token can NOT deposit into lost-found.".as_bytes());
+    /// save token to lost-found of given account, no need to care about storage.
+    /// Account can claim it back with `claim_lostfound`.
+    /// The contract pays the storage, so an account keeps at most `MAX_LOSTFOUND_UNLISTED_TOKENS`
+    /// non-whitelisted tokens. Beyond that, the token goes to owner's inner account to be returned by hand.
+    /// Tokens stay in the liability either way.
+    pub(crate) fn internal_lostfound(&mut self, account_id: &AccountId, token_id: &AccountId, amount: u128) {
+        let mut tokens = self.lostfound.get(account_id).unwrap_or_default();
+        if !tokens.contains_key(token_id)
+            && !self.whitelisted_tokens.contains(token_id)
+            && tokens
+                .keys()
+                .filter(|token_id| !self.whitelisted_tokens.contains(token_id))
+                .count()
+                >= MAX_LOSTFOUND_UNLISTED_TOKENS
+        {
+            env::log(
+                format!(
+                    "Lost-found of {} is full, {} of token {} goes to owner's account.",
+                    account_id, amount, token_id
+                )
+                .as_bytes(),
+            );
+            let owner_id = self.owner_id.clone();
+            let mut owner = self.internal_unwrap_or_default_account(&owner_id);
+            owner.deposit(token_id, amount);
+            self.accounts.insert(&owner_id, &owner.into());
+            return;
         }
-        
+        *tokens.entry(token_id.clone()).or_insert(0) += amount;
+        self.lostfound.insert(account_id, &tokens);
+        Event::Lostfound {
+            account_id,
+            token_id,
+            amount: U128(amount),
+        }
+        .emit();
     }
     
 
@@ -392,6 +683,13 @@ impl Contract {
         );
         account.deposit(token_id, amount);
         self.internal_save_account(&sender_id, account);
+        self.internal_increase_liability(token_id, amount);
+        Event::Deposit {
+            account_id: sender_id,
+            token_id,
+            amount: U128(amount),
+        }
+        .emit();
     }
 
     pub fn internal_get_account(&self, account_id: &AccountId) -> Option<Account> {
@@ -428,9 +726,21 @@ impl Contract {
         sender_id: &AccountId,
         token_id: &AccountId,
         amount: Balance,
+    ) -> Promise {
+        self.internal_send_tokens_to(sender_id, sender_id, token_id, amount)
+    }
+
+    /// Sends given amount of sender's tokens to receiver and if it fails, returns it back to sender's balance.
+    /// Tokens must already be subtracted from internal balance.
+    pub(crate) fn internal_send_tokens_to(
+        &self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
     ) -> Promise {
         ext_fungible_token::ft_transfer(
-            sender_id.clone(),
+            receiver_id.clone(),
             U128(amount),
             None,
             token_id,
@@ -446,4 +756,35 @@ impl Contract {
             GAS_FOR_RESOLVE_TRANSFER,
         ))
     }
+
+    /// Sends given amount of sender's tokens to receiver with `ft_transfer_call`,
+    /// the part not used by receiver returns to sender's balance.
+    /// Tokens must already be subtracted from internal balance,
+    /// and prepaid gas must be checked to cover `GAS_FOR_FT_TRANSFER_CALL`.
+    pub(crate) fn internal_send_tokens_call(
+        &self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+        msg: String,
+    ) -> Promise {
+        ext_fungible_token::ft_transfer_call(
+            receiver_id.clone(),
+            U128(amount),
+            None,
+            msg,
+            token_id,
+            1,
+            env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL,
+        )
+        .then(ext_self::exchange_callback_post_withdraw_call(
+            token_id.clone(),
+            sender_id.clone(),
+            U128(amount),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
 }
@@ -1,4 +1,8 @@
-use near_sdk::{env, AccountId};
+use near_sdk::json_types::U128;
+use near_sdk::{env, AccountId, Balance};
+
+use crate::events::Event;
+use crate::pool::Pool;
 
 /// Maintain information about fees.
 pub struct AdminFees {
@@ -23,4 +27,39 @@ impl AdminFees {
     pub fn zero() -> Self {
         Self::new(0)
     }
+
+    /// Returns shares held in given pool by the exchange and the referral (0 if none).
+    pub fn receiver_shares(&self, pool: &Pool) -> (Balance, Balance) {
+        (
+            pool.share_balances(&self.exchange_id),
+            self.referral_id
+                .as_ref()
+                .map(|referral_id| pool.share_balances(referral_id))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Emits fee events for the shares minted to the exchange and the referral
+    /// since `prev_shares` were taken with `receiver_shares`.
+    pub fn emit_minted_shares(&self, pool_id: u64, pool: &Pool, prev_shares: (Balance, Balance)) {
+        let (exchange_shares, referral_shares) = self.receiver_shares(pool);
+        if exchange_shares > prev_shares.0 {
+            Event::AdminFeeMinted {
+                pool_id,
+                account_id: &self.exchange_id,
+                shares: U128(exchange_shares - prev_shares.0),
+            }
+            .emit();
+        }
+        if let Some(referral_id) = &self.referral_id {
+            if referral_shares > prev_shares.1 {
+                Event::ReferralFeeMinted {
+                    pool_id,
+                    referral_id,
+                    shares: U128(referral_shares - prev_shares.1),
+                }
+                .emit();
+            }
+        }
+    }
 }
@@ -0,0 +1,312 @@
+//! Permissionless creation of stable pools backed by a bond.
+//!
+//! Anyone can add a stable pool by posting a bond, in NEAR or in the configured REF token
+//! taken from the creator's inner account, with parameters inside tighter bounds than
+//! `add_stable_swap_pool` allows. The decimals given are checked against each token's `ft_metadata`,
+//! and the pool stays closed to swaps until guardians approve it or the bond period ends.
+//! Guardians can reject the pool during the period, which keeps it closed and sends the bond to the owner.
+//!
+//! A bond in REF stays counted in the token's liability while it is held,
+//! so it can't be retrieved as surplus.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise};
+
+use crate::utils::{ext_ft_metadata, ext_self, GAS_FOR_BASIC_OP, NO_DEPOSIT};
+use crate::*;
+
+/// Amp factor range of bonded pools.
+pub const BONDED_MIN_AMP: u64 = 10;
+pub const BONDED_MAX_AMP: u64 = 5_000;
+/// Total fee range of bonded pools, in bps.
+pub const BONDED_MIN_FEE: u32 = 1;
+pub const BONDED_MAX_FEE: u32 = 50;
+/// Token decimals range of bonded pools.
+pub const BONDED_MIN_DECIMAL: u8 = 6;
+pub const BONDED_MAX_DECIMAL: u8 = 18;
+/// Max number of tokens in a bonded pool.
+pub const BONDED_MAX_TOKENS: usize = 4;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum PoolBondState {
+    /// Token decimals are being checked against `ft_metadata`.
+    Verifying,
+    /// Waiting for guardians, the pool opens to swaps at the end of the bond period.
+    Pending,
+    /// Rejected by guardians or on wrong decimals, the pool stays closed to swaps.
+    Rejected,
+    /// Approved by guardians or the period is over, bond returned to the creator.
+    /// Only reported in events, the bond record is removed.
+    Released,
+}
+
+/// Bond required to create a pool, set by owner.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PoolBondConfig {
+    /// Bond in NEAR, attached on top of storage.
+    pub near_amount: U128,
+    /// REF token, None if bonds in REF are not accepted.
+    pub ref_token_id: Option<AccountId>,
+    /// Bond in REF, taken from the creator's inner account.
+    pub ref_amount: U128,
+    /// Seconds guardians have to review a pool.
+    pub period_sec: u32,
+}
+
+impl Default for PoolBondConfig {
+    fn default() -> Self {
+        Self {
+            near_amount: U128(20_000_000_000_000_000_000_000_000),
+            ref_token_id: None,
+            ref_amount: U128(0),
+            period_sec: 3 * 24 * 3600,
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PoolBond {
+    pub creator_id: AccountId,
+    /// None for a bond in NEAR.
+    pub token_id: Option<AccountId>,
+    /// Zero once the bond left the contract on rejection.
+    pub amount: U128,
+    pub state: PoolBondState,
+    /// Block timestamp in seconds the pool opens to swaps, set when decimals are verified.
+    pub unlock_at_sec: u64,
+}
+
+fn now_sec() -> u64 {
+    env::block_timestamp() / 1_000_000_000
+}
+
+impl Contract {
+    /// Whether given pool is open to swaps, pools created without a bond always are unless deactivated.
+    pub(crate) fn is_pool_active(&self, pool_id: u64) -> bool {
+        if self.deactivated_pools.contains(&pool_id) {
+            return false;
+        }
+        match self.pool_bonds.get(&pool_id) {
+            Some(bond) => bond.state == PoolBondState::Pending && now_sec() >= bond.unlock_at_sec,
+            None => true,
+        }
+    }
+
+    /// Sends the bond to given account. Bond in REF goes to its inner account,
+    /// or to its lost-found if the account isn't registered in the token.
+    fn internal_release_pool_bond(&mut self, bond: &PoolBond, receiver_id: &AccountId) {
+        if bond.amount.0 == 0 {
+            return;
+        }
+        match &bond.token_id {
+            None => {
+                Promise::new(receiver_id.clone()).transfer(bond.amount.0);
+            }
+            Some(token_id) => match self.internal_get_account(receiver_id) {
+                Some(mut account) if account.get_balance(token_id).is_some() => {
+                    account.deposit(token_id, bond.amount.0);
+                    self.internal_save_account(receiver_id, account);
+                }
+                _ => self.internal_lostfound(receiver_id, token_id, bond.amount.0),
+            },
+        }
+    }
+
+    fn internal_update_pool_bond(&mut self, pool_id: u64, bond: &PoolBond, state: PoolBondState, amount: Balance) {
+        if state == PoolBondState::Released {
+            self.pool_bonds.remove(&pool_id);
+        } else {
+            self.pool_bonds.insert(&pool_id, bond);
+        }
+        Event::PoolBond {
+            pool_id,
+            creator_id: &bond.creator_id,
+            token_id: bond.token_id.as_ref(),
+            amount: U128(amount),
+            state,
+        }
+        .emit();
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Adds new "Stable Pool" without owner's permission, backed by a bond of the caller.
+    /// Parameters have tighter bounds than `add_stable_swap_pool`, and decimals are checked against
+    /// `ft_metadata` of the tokens. The pool takes liquidity right away, but swaps are closed
+    /// until guardians approve it or the bond period ends.
+    /// bond_token: None to bond in NEAR, attached on top of storage, or the REF token of the config
+    /// to bond from the caller's inner account.
+    /// Returns id of the new pool.
+    #[payable]
+    pub fn add_bonded_stable_swap_pool(
+        &mut self,
+        tokens: Vec<ValidAccountId>,
+        decimals: Vec<u8>,
+        fee: u32,
+        amp_factor: u64,
+        bond_token: Option<ValidAccountId>,
+    ) -> u64 {
+        self.assert_contract_running();
+        check_token_duplicates(&tokens);
+        assert!(
+            (2..=BONDED_MAX_TOKENS).contains(&tokens.len()) && decimals.len() == tokens.len(),
+            "{}",
+            ERR72_BONDED_POOL_ILLEGAL
+        );
+        assert!(
+            decimals.iter().all(|d| (BONDED_MIN_DECIMAL..=BONDED_MAX_DECIMAL).contains(d))
+                && (BONDED_MIN_FEE..=BONDED_MAX_FEE).contains(&fee)
+                && (BONDED_MIN_AMP..=BONDED_MAX_AMP).contains(&amp_factor),
+            "{}",
+            ERR72_BONDED_POOL_ILLEGAL
+        );
+        self.assert_token_decimals(&tokens, &decimals);
+        let prev_storage = env::storage_usage();
+        let creator_id = env::predecessor_account_id();
+        let config = self.pool_bond_config.clone();
+        let (bond_token, bond_amount, storage_deposit) = match bond_token {
+            None => {
+                let deposit = env::attached_deposit();
+                assert!(deposit >= config.near_amount.0, "{}", ERR73_INSUFFICIENT_BOND);
+                (None, config.near_amount.0, deposit - config.near_amount.0)
+            }
+            Some(token_id) => {
+                let token_id: AccountId = token_id.into();
+                assert!(
+                    config.ref_token_id.as_ref() == Some(&token_id) && config.ref_amount.0 > 0,
+                    "{}",
+                    ERR72_BONDED_POOL_ILLEGAL
+                );
+                // Bond stays in the token's liability, only moved out of the account.
+                let mut account = self.internal_unwrap_account(&creator_id);
+                account.withdraw(&token_id, config.ref_amount.0);
+                self.internal_save_account(&creator_id, account);
+                (Some(token_id), config.ref_amount.0, env::attached_deposit())
+            }
+        };
+
+        // Bond is recorded ahead of the pool, under the id the pool gets.
+        let pool_id = self.pools.len();
+        let bond = PoolBond {
+            creator_id,
+            token_id: bond_token,
+            amount: U128(bond_amount),
+            state: PoolBondState::Verifying,
+            unlock_at_sec: 0,
+        };
+        self.internal_update_pool_bond(pool_id, &bond, PoolBondState::Verifying, bond_amount);
+        let pool = Pool::StableSwapPool(StableSwapPool::new(
+            pool_id as u32,
+            tokens.clone(),
+            decimals.clone(),
+            amp_factor as u128,
+            fee,
+        ));
+        self.internal_add_pool_with_budget(pool, Some(bond.creator_id.clone()), prev_storage, storage_deposit);
+
+        let mut metadata = ext_ft_metadata::ft_metadata(tokens[0].as_ref(), NO_DEPOSIT, GAS_FOR_BASIC_OP);
+        for token_id in tokens.iter().skip(1) {
+            metadata = metadata.and(ext_ft_metadata::ft_metadata(token_id.as_ref(), NO_DEPOSIT, GAS_FOR_BASIC_OP));
+        }
+        metadata.then(ext_self::exchange_callback_check_pool_decimals(
+            pool_id,
+            decimals,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_BASIC_OP,
+        ));
+        pool_id
+    }
+
+    /// Starts the bond period if every token reports the decimals given at pool creation.
+    /// Otherwise the pool is rejected and the bond returned to the creator.
+    /// Metadata read is recorded in the token registry.
+    #[private]
+    pub fn exchange_callback_check_pool_decimals(&mut self, pool_id: u64, decimals: Vec<u8>) -> bool {
+        let mut bond = self.pool_bonds.get(&pool_id).expect(ERR75_NO_POOL_BOND);
+        assert!(bond.state == PoolBondState::Verifying, "{}", ERR76_ILLEGAL_BOND_STATE);
+        let tokens = self.pools.get(pool_id).expect("ERR_NO_POOL").tokens().to_vec();
+        let mut verified = env::promise_results_count() == tokens.len() as u64;
+        for (index, token_id) in tokens.iter().enumerate() {
+            let metadata = self.internal_record_token_metadata(token_id, env::promise_result(index as u64));
+            verified &= metadata.map(|metadata| Some(&metadata.decimals) == decimals.get(index))
+                .unwrap_or(false);
+        }
+        if verified {
+            bond.state = PoolBondState::Pending;
+            bond.unlock_at_sec = now_sec() + self.pool_bond_config.period_sec as u64;
+            self.internal_update_pool_bond(pool_id, &bond, PoolBondState::Pending, bond.amount.0);
+        } else {
+            let amount = bond.amount.0;
+            let creator_id = bond.creator_id.clone();
+            self.internal_release_pool_bond(&bond, &creator_id);
+            bond.state = PoolBondState::Rejected;
+            bond.amount = U128(0);
+            self.internal_update_pool_bond(pool_id, &bond, PoolBondState::Rejected, amount);
+        }
+        verified
+    }
+
+    /// Opens given pool to swaps and returns the bond to its creator.
+    /// Only can be called by owner or guardians, once decimals are verified.
+    pub fn approve_bonded_pool(&mut self, pool_id: u64) {
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        let bond = self.pool_bonds.get(&pool_id).expect(ERR75_NO_POOL_BOND);
+        assert!(bond.state == PoolBondState::Pending, "{}", ERR76_ILLEGAL_BOND_STATE);
+        self.internal_release_pool_bond(&bond, &bond.creator_id);
+        self.internal_update_pool_bond(pool_id, &bond, PoolBondState::Released, bond.amount.0);
+    }
+
+    /// Keeps given pool closed to swaps and sends the bond to owner.
+    /// Only can be called by owner or guardians, before the bond period ends.
+    pub fn reject_bonded_pool(&mut self, pool_id: u64) {
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        let mut bond = self.pool_bonds.get(&pool_id).expect(ERR75_NO_POOL_BOND);
+        assert!(
+            bond.state == PoolBondState::Verifying
+                || (bond.state == PoolBondState::Pending && now_sec() < bond.unlock_at_sec),
+            "{}",
+            ERR76_ILLEGAL_BOND_STATE
+        );
+        let amount = bond.amount.0;
+        let owner_id = self.owner_id.clone();
+        self.internal_release_pool_bond(&bond, &owner_id);
+        bond.state = PoolBondState::Rejected;
+        bond.amount = U128(0);
+        self.internal_update_pool_bond(pool_id, &bond, PoolBondState::Rejected, amount);
+    }
+
+    /// Returns the bond of given pool to its creator once the bond period is over.
+    /// Anyone can call it, the pool is open to swaps anyway at that time.
+    pub fn claim_pool_bond(&mut self, pool_id: u64) {
+        let bond = self.pool_bonds.get(&pool_id).expect(ERR75_NO_POOL_BOND);
+        assert!(self.is_pool_active(pool_id), "{}", ERR76_ILLEGAL_BOND_STATE);
+        self.internal_release_pool_bond(&bond, &bond.creator_id);
+        self.internal_update_pool_bond(pool_id, &bond, PoolBondState::Released, bond.amount.0);
+    }
+
+    /// Sets the bond required for new bonded pools. Only can be called by owner.
+    pub fn set_pool_bond_config(&mut self, config: PoolBondConfig) {
+        self.assert_owner();
+        self.pool_bond_config = config;
+    }
+
+    pub fn get_pool_bond_config(&self) -> PoolBondConfig {
+        self.pool_bond_config.clone()
+    }
+
+    /// Returns the bond of given pool, None if it was created without one or the bond was released.
+    pub fn get_pool_bond(&self, pool_id: u64) -> Option<PoolBond> {
+        self.pool_bonds.get(&pool_id)
+    }
+}
@@ -0,0 +1,318 @@
+//! Trading delegation: an account lets another account swap on its inner balances.
+//!
+//! A delegate can only run swap actions, on tokens listed in the delegation,
+//! and the net amount of each token leaving the grantor's account is capped per period.
+//! Grantor can also bound each swap's output to a max slippage from the pool's TWAP.
+//! Delegated swaps carry no referral, so the delegate can't take a cut of the grantor's fees.
+//! Withdrawals, transfers and liquidity operations stay with the grantor.
+
+use std::collections::HashMap;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, Promise, Timestamp};
+
+use crate::action::{Action, ActionResult};
+use crate::utils::FEE_DIVISOR;
+use crate::*;
+
+/// Cap and spent amount of one token in current period.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct TokenQuota {
+    pub cap: Balance,
+    pub spent: Balance,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct TradingDelegation {
+    /// Tokens the delegate may trade, with their caps per period.
+    pub quotas: HashMap<AccountId, TokenQuota>,
+    /// Length of a period in seconds.
+    pub period_sec: u32,
+    /// Start of current period.
+    pub period_start: Timestamp,
+    /// Max shortfall of a swap's output from the amount at the pool's TWAP, in bps, None if unbounded.
+    pub max_slippage_bps: Option<u32>,
+    /// Window of the TWAP swaps are checked against.
+    pub twap_window_sec: u32,
+}
+
+impl TradingDelegation {
+    /// Starts a new period if current one is over.
+    fn refresh(&mut self) {
+        let now = env::block_timestamp();
+        if now >= self.period_start + to_nano(self.period_sec) {
+            self.period_start = now;
+            for quota in self.quotas.values_mut() {
+                quota.spent = 0;
+            }
+        }
+    }
+
+    /// Charges decrease of each token balance against its cap.
+    /// Balances that grew (e.g. the token bought) are not credited back.
+    fn spend(&mut self, prev_balances: &[(AccountId, Balance)], account: &Account) {
+        for (token_id, prev_balance) in prev_balances {
+            let balance = account.get_balance(token_id).unwrap_or(0);
+            if balance < *prev_balance {
+                let quota = self.quotas.get_mut(token_id).unwrap();
+                quota.spent += prev_balance - balance;
+                assert!(quota.spent <= quota.cap, "{}", ERR93_DELEGATION_CAP_EXCEEDED);
+            }
+        }
+    }
+}
+
+fn to_nano(sec: u32) -> Timestamp {
+    Timestamp::from(sec) * 1_000_000_000
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct TokenQuotaInfo {
+    pub cap: U128,
+    /// Amount still available in current period.
+    pub remaining: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct TradingDelegationInfo {
+    pub quotas: HashMap<AccountId, TokenQuotaInfo>,
+    pub period_sec: u32,
+    pub period_start: U64,
+    pub max_slippage_bps: Option<u32>,
+    pub twap_window_sec: u32,
+}
+
+impl From<TradingDelegation> for TradingDelegationInfo {
+    fn from(mut delegation: TradingDelegation) -> Self {
+        delegation.refresh();
+        Self {
+            quotas: delegation
+                .quotas
+                .into_iter()
+                .map(|(token_id, quota)| {
+                    (
+                        token_id,
+                        TokenQuotaInfo {
+                            cap: U128(quota.cap),
+                            remaining: U128(quota.cap - quota.spent),
+                        },
+                    )
+                })
+                .collect(),
+            period_sec: delegation.period_sec,
+            period_start: U64(delegation.period_start),
+            max_slippage_bps: delegation.max_slippage_bps,
+            twap_window_sec: delegation.twap_window_sec,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Allows `delegate_id` to swap on caller's inner account.
+    /// Only tokens in `token_caps` can be traded, and at most the cap of each can be spent per `period_sec`.
+    /// With `max_slippage_bps`, each swap must get at least the amount at the pool's TWAP over
+    /// `twap_window_sec` less that slippage, so swaps are only possible on pools with a price oracle.
+    /// Replaces any previous delegation to the same account and starts a new period.
+    /// Attached NEAR should be enough to cover the storage, the rest is refunded.
+    #[payable]
+    pub fn grant_trading_delegation(
+        &mut self,
+        delegate_id: ValidAccountId,
+        token_caps: HashMap<AccountId, U128>,
+        period_sec: u32,
+        max_slippage_bps: Option<u32>,
+        twap_window_sec: u32,
+    ) {
+        assert!(
+            env::attached_deposit() > 0,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        self.assert_contract_running();
+        let grantor_id = env::predecessor_account_id();
+        let delegate_id: AccountId = delegate_id.into();
+        assert!(
+            grantor_id != delegate_id
+                && !token_caps.is_empty()
+                && token_caps
+                    .keys()
+                    .all(|token_id| env::is_valid_account_id(token_id.as_bytes()))
+                && period_sec > 0
+                && !matches!(max_slippage_bps, Some(bps) if bps > FEE_DIVISOR || twap_window_sec == 0),
+            "{}",
+            ERR94_ILLEGAL_DELEGATION
+        );
+        self.internal_unwrap_account(&grantor_id);
+
+        let prev_storage = env::storage_usage();
+        let delegation = TradingDelegation {
+            quotas: token_caps
+                .iter()
+                .map(|(token_id, cap)| (token_id.clone(), TokenQuota { cap: cap.0, spent: 0 }))
+                .collect(),
+            period_sec,
+            period_start: env::block_timestamp(),
+            max_slippage_bps,
+            twap_window_sec,
+        };
+        let mut delegations = self.trading_delegations.get(&grantor_id).unwrap_or_default();
+        delegations.insert(delegate_id.clone(), delegation);
+        self.trading_delegations.insert(&grantor_id, &delegations);
+        self.internal_check_storage(prev_storage);
+
+        Event::TradingDelegationGranted {
+            grantor_id: &grantor_id,
+            delegate_id: &delegate_id,
+            token_caps: token_caps.iter().map(|(k, v)| (k, *v)).collect(),
+            period_sec,
+        }
+        .emit();
+    }
+
+    /// Removes the delegation given to `delegate_id`, refunding its storage to the caller.
+    #[payable]
+    pub fn revoke_trading_delegation(&mut self, delegate_id: ValidAccountId) {
+        assert_one_yocto();
+        let grantor_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let mut delegations = self.trading_delegations.get(&grantor_id).unwrap_or_default();
+        delegations
+            .remove(delegate_id.as_ref())
+            .expect(ERR91_NO_DELEGATION);
+        if delegations.is_empty() {
+            self.trading_delegations.remove(&grantor_id);
+        } else {
+            self.trading_delegations.insert(&grantor_id, &delegations);
+        }
+        let refund = (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        if refund > 0 {
+            Promise::new(grantor_id.clone()).transfer(refund);
+        }
+        Event::TradingDelegationRevoked {
+            grantor_id: &grantor_id,
+            delegate_id: delegate_id.as_ref(),
+        }
+        .emit();
+    }
+
+    /// Executes swap actions on the inner account of `grantor_id`, on behalf of it.
+    /// Caller must have a delegation from grantor covering every token in the actions.
+    /// Swaps are made without referral, and within the delegation's max slippage if any.
+    pub fn delegated_execute_actions(
+        &mut self,
+        grantor_id: ValidAccountId,
+        actions: Vec<Action>,
+    ) -> ActionResult {
+        self.assert_contract_running();
+        let grantor_id: AccountId = grantor_id.into();
+        let delegate_id = env::predecessor_account_id();
+        let mut delegations = self.trading_delegations.get(&grantor_id).unwrap_or_default();
+        let delegation = delegations.get_mut(&delegate_id).expect(ERR91_NO_DELEGATION);
+        delegation.refresh();
+
+        let mut account = self.internal_unwrap_account(&grantor_id);
+        for action in &actions {
+            for token in action.tokens() {
+                assert!(
+                    delegation.quotas.contains_key(&token),
+                    "{}",
+                    ERR92_TOKEN_NOT_DELEGATED
+                );
+                // Delegate can't pay storage of grantor, same rule as trading with access key.
+                assert!(
+                    account.get_balance(&token).is_some()
+                        || self.whitelisted_tokens.contains(&token),
+                    "{}",
+                    ERR27_DEPOSIT_NEEDED
+                );
+            }
+        }
+        let prev_balances: Vec<(AccountId, Balance)> = delegation
+            .quotas
+            .keys()
+            .map(|token_id| (token_id.clone(), account.get_balance(token_id).unwrap_or(0)))
+            .collect();
+
+        let mut result = ActionResult::None;
+        for action in &actions {
+            let Action::Swap(swap_action) = action;
+            let min_amount_out = delegation.max_slippage_bps.map(|max_slippage_bps| {
+                // Missing amount of a first action fails in the swap itself.
+                let amount_in = match (&swap_action.amount_in, &result) {
+                    (Some(amount_in), _) | (None, ActionResult::Amount(amount_in)) => amount_in.0,
+                    (None, ActionResult::None) => 0,
+                };
+                self.internal_twap_min_amount_out(
+                    swap_action.pool_id,
+                    &swap_action.token_in,
+                    amount_in,
+                    &swap_action.token_out,
+                    delegation.twap_window_sec,
+                    max_slippage_bps,
+                )
+                .unwrap_or_else(|err| env::panic(err.as_bytes()))
+            });
+            result = self.internal_execute_actions(
+                &grantor_id,
+                &mut account,
+                &None,
+                std::slice::from_ref(action),
+                result,
+            );
+            if let (Some(min_amount_out), ActionResult::Amount(amount_out)) = (min_amount_out, &result) {
+                assert!(amount_out.0 >= min_amount_out, "{}", ERR90_DELEGATION_SLIPPAGE_EXCEEDED);
+            }
+        }
+        delegation.spend(&prev_balances, &account);
+        self.internal_save_account(&grantor_id, account);
+        self.trading_delegations.insert(&grantor_id, &delegations);
+        result
+    }
+
+    /// Execute set of swap actions on the inner account of `grantor_id`, on behalf of it.
+    pub fn delegated_swap(
+        &mut self,
+        grantor_id: ValidAccountId,
+        actions: Vec<SwapAction>,
+    ) -> U128 {
+        assert_ne!(actions.len(), 0, "ERR_AT_LEAST_ONE_SWAP");
+        U128(
+            self.delegated_execute_actions(
+                grantor_id,
+                actions.into_iter().map(Action::Swap).collect(),
+            )
+            .to_amount(),
+        )
+    }
+
+    /// Returns delegation given by `grantor_id` to `delegate_id` if any.
+    pub fn get_trading_delegation(
+        &self,
+        grantor_id: ValidAccountId,
+        delegate_id: ValidAccountId,
+    ) -> Option<TradingDelegationInfo> {
+        self.trading_delegations
+            .get(grantor_id.as_ref())
+            .and_then(|mut delegations| delegations.remove(delegate_id.as_ref()))
+            .map(|delegation| delegation.into())
+    }
+
+    /// Returns all delegations given by `grantor_id`, keyed by delegate.
+    pub fn get_trading_delegations(
+        &self,
+        grantor_id: ValidAccountId,
+    ) -> HashMap<AccountId, TradingDelegationInfo> {
+        self.trading_delegations
+            .get(grantor_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(delegate_id, delegation)| (delegate_id, delegation.into()))
+            .collect()
+    }
+}
@@ -1,3 +1,5 @@
+use near_sdk::serde::{Deserialize, Serialize};
+
 // Storage errors.
 
 pub const ERR10_ACC_NOT_REGISTERED: &str = "E10: account not registered";
@@ -5,6 +7,8 @@ pub const ERR11_INSUFFICIENT_STORAGE: &str = "E11: insufficient $NEAR storage de
 pub const ERR12_TOKEN_NOT_WHITELISTED: &str = "E12: token not whitelisted";
 pub const ERR13_LP_NOT_REGISTERED: &str = "E13: LP not registered";
 pub const ERR14_LP_ALREADY_REGISTERED: &str = "E14: LP already registered";
+pub const ERR15_NON_ZERO_LP_SHARES: &str = "E15: non-zero lp shares";
+pub const ERR16_TOKEN_DENIED: &str = "E16: token denied";
 
 // Accounts.
 
@@ -20,6 +24,7 @@ pub const ERR27_DEPOSIT_NEEDED: &str =
     "E27: attach 1yN to swap tokens not in whitelist";
 pub const ERR28_WRONG_MSG_FORMAT: &str = "E28: Illegal msg in ft_transfer_call";
 pub const ERR29_ILLEGAL_WITHDRAW_AMOUNT: &str = "E29: Illegal withdraw amount";
+pub const ERR30_NO_LOSTFOUND: &str = "E30: nothing to claim in lost-found";
 
 // Liquidity operations.
 
@@ -28,13 +33,31 @@ pub const ERR32_ZERO_SHARES: &str = "E32: minting zero shares";
 // [AUDIT_07]
 pub const ERR33_TRANSFER_TO_SELF: &str = "E33: transfer to self";
 pub const ERR34_INSUFFICIENT_LP_SHARES: &str = "E34: insufficient lp shares";
+pub const ERR35_INSUFFICIENT_ALLOWANCE: &str = "E35: insufficient allowance";
+pub const ERR36_NO_ALLOWANCE: &str = "E36: allowance not found";
+pub const ERR37_CREATOR_SHARES_RESERVED: &str = "E37: exchange shares reserved for pool creator";
+pub const ERR38_ILLEGAL_CREATOR_FEE: &str = "E38: illegal creator fee share";
+pub const ERR39_NO_FEE_HARVEST_CONFIG: &str = "E39: fee harvest not configured";
+pub const ERR40_ILLEGAL_FEE_HARVEST_CONFIG: &str = "E40: illegal fee harvest config";
 
 // Action result.
 
 pub const ERR41_WRONG_ACTION_RESULT: &str = "E41: wrong action result type";
 
+// Withdraw batch.
+
+pub const ERR42_WITHDRAW_BATCH_TOO_LARGE: &str = "E42: too many tokens in withdraw batch";
+pub const ERR43_WITHDRAW_BATCH_GAS: &str = "E43: not enough gas for withdraw batch";
+pub const ERR44_TRANSFER_CALL_GAS: &str = "E44: not enough gas for transfer call";
+
 // Contract Level
 pub const ERR51_CONTRACT_PAUSED: &str = "E51: contract paused";
+pub const ERR52_BALANCE_UNAVAILABLE: &str = "E52: failed to get contract balance on token";
+pub const ERR53_LIABILITY_NOT_SEEDED: &str = "E53: token liability not seeded";
+pub const ERR54_LIABILITY_SEEDED: &str = "E54: token liability already seeded";
+pub const ERR55_EXCEED_SURPLUS: &str = "E55: amount exceeds token surplus";
+pub const ERR56_LIABILITY_UNDERFLOW: &str = "E56: token going out exceeds its liability";
+pub const ERR57_RETRIEVAL_TOO_EARLY: &str = "E57: retrieval requested too recently";
 
 // Swap
 pub const ERR60_DECIMAL_ILLEGAL: &str = "E60: illegal decimal";
@@ -50,11 +73,145 @@ pub const ERR69_MIN_RESERVE: &str = "E69: pool reserved token balance less than
 pub const ERR70_SWAP_OUT_CALC_ERR: &str = "E70: encounter err when calc swap out";
 pub const ERR71_SWAP_DUP_TOKENS: &str = "E71: illegal swap with duplicated tokens";
 
+// Bonded pools
+pub const ERR72_BONDED_POOL_ILLEGAL: &str = "E72: illegal config for bonded pool";
+pub const ERR73_INSUFFICIENT_BOND: &str = "E73: insufficient pool bond";
+pub const ERR74_POOL_NOT_ACTIVE: &str = "E74: pool is waiting for approval";
+pub const ERR75_NO_POOL_BOND: &str = "E75: pool bond not found";
+pub const ERR76_ILLEGAL_BOND_STATE: &str = "E76: illegal pool bond state for this action";
+
+// Token metadata
+pub const ERR77_TOKEN_DECIMALS_MISMATCH: &str = "E77: decimals differ from token metadata";
+
+// Token migration
+pub const ERR78_NO_TOKEN_MIGRATION: &str = "E78: token migration not found";
+pub const ERR79_TOKEN_MIGRATION_NOT_READY: &str = "E79: token migration not verified or still timelocked";
+pub const ERR80_ILLEGAL_TOKEN_MIGRATION: &str = "E80: illegal token migration";
+
 // pool manage
 pub const ERR81_AMP_IN_LOCK: &str = "E81: amp is currently in lock";
 pub const ERR82_INSUFFICIENT_RAMP_TIME: &str = "E82: insufficient ramp time";
 pub const ERR83_INVALID_AMP_FACTOR: &str = "E83: invalid amp factor";
 pub const ERR84_AMP_LARGE_CHANGE: &str = "E84: amp factor change is too large";
+pub const ERR85_ILLEGAL_STATS_WINDOW: &str = "E85: illegal stats window";
+pub const ERR86_POOL_NOT_FOUND: &str = "E86: pool not found";
+pub const ERR87_EMPTY_POOL: &str = "E87: pool has no liquidity";
+pub const ERR88_NOT_SUPPORTED: &str = "E88: not supported by this pool kind";
+pub const ERR89_ILLEGAL_LIQUIDITY_MIGRATION: &str = "E89: pools must differ and hold the same tokens";
+
+// Trading delegation
+pub const ERR90_DELEGATION_SLIPPAGE_EXCEEDED: &str = "E90: delegated swap exceeds max slippage from twap";
+pub const ERR91_NO_DELEGATION: &str = "E91: trading delegation not found";
+pub const ERR92_TOKEN_NOT_DELEGATED: &str = "E92: token not allowed by delegation";
+pub const ERR93_DELEGATION_CAP_EXCEEDED: &str = "E93: delegation cap exceeded in this period";
+pub const ERR94_ILLEGAL_DELEGATION: &str = "E94: illegal delegation";
+
+// Price oracle
+pub const ERR95_ORACLE_NOT_ENABLED: &str = "E95: price oracle not enabled on pool";
+pub const ERR96_ILLEGAL_ORACLE_CAPACITY: &str = "E96: illegal oracle capacity";
+pub const ERR97_ORACLE_HISTORY_TOO_SHORT: &str = "E97: oracle history shorter than window";
+pub const ERR98_TWAP_UNAVAILABLE: &str = "E98: twap unavailable";
 
 // Permissions
 pub const ERR100_NOT_ALLOWED: &str = "E100: no permission to invoke this";
+
+/// Declares `ErrorCode` with one value per error message.
+macro_rules! error_codes {
+    ($($code:ident => $message:ident,)*) => {
+        /// Typed errors returned by `try_*` views, one value per E-code.
+        #[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+        #[serde(crate = "near_sdk::serde")]
+        #[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+        pub enum ErrorCode {
+            $($code,)*
+        }
+
+        impl ErrorCode {
+            /// Returns the error message the panicking methods use.
+            pub fn message(&self) -> &'static str {
+                match self {
+                    $(ErrorCode::$code => $message,)*
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    E10AccNotRegistered => ERR10_ACC_NOT_REGISTERED,
+    E11InsufficientStorage => ERR11_INSUFFICIENT_STORAGE,
+    E12TokenNotWhitelisted => ERR12_TOKEN_NOT_WHITELISTED,
+    E13LpNotRegistered => ERR13_LP_NOT_REGISTERED,
+    E14LpAlreadyRegistered => ERR14_LP_ALREADY_REGISTERED,
+    E15NonZeroLpShares => ERR15_NON_ZERO_LP_SHARES,
+    E16TokenDenied => ERR16_TOKEN_DENIED,
+    E21TokenNotReg => ERR21_TOKEN_NOT_REG,
+    E22NotEnoughTokens => ERR22_NOT_ENOUGH_TOKENS,
+    E24NonZeroTokenBalance => ERR24_NON_ZERO_TOKEN_BALANCE,
+    E25CallbackPostWithdrawInvalid => ERR25_CALLBACK_POST_WITHDRAW_INVALID,
+    E27DepositNeeded => ERR27_DEPOSIT_NEEDED,
+    E28WrongMsgFormat => ERR28_WRONG_MSG_FORMAT,
+    E29IllegalWithdrawAmount => ERR29_ILLEGAL_WITHDRAW_AMOUNT,
+    E30NoLostfound => ERR30_NO_LOSTFOUND,
+    E31ZeroAmount => ERR31_ZERO_AMOUNT,
+    E32ZeroShares => ERR32_ZERO_SHARES,
+    E33TransferToSelf => ERR33_TRANSFER_TO_SELF,
+    E34InsufficientLpShares => ERR34_INSUFFICIENT_LP_SHARES,
+    E35InsufficientAllowance => ERR35_INSUFFICIENT_ALLOWANCE,
+    E36NoAllowance => ERR36_NO_ALLOWANCE,
+    E37CreatorSharesReserved => ERR37_CREATOR_SHARES_RESERVED,
+    E38IllegalCreatorFee => ERR38_ILLEGAL_CREATOR_FEE,
+    E39NoFeeHarvestConfig => ERR39_NO_FEE_HARVEST_CONFIG,
+    E40IllegalFeeHarvestConfig => ERR40_ILLEGAL_FEE_HARVEST_CONFIG,
+    E41WrongActionResult => ERR41_WRONG_ACTION_RESULT,
+    E42WithdrawBatchTooLarge => ERR42_WITHDRAW_BATCH_TOO_LARGE,
+    E43WithdrawBatchGas => ERR43_WITHDRAW_BATCH_GAS,
+    E44TransferCallGas => ERR44_TRANSFER_CALL_GAS,
+    E51ContractPaused => ERR51_CONTRACT_PAUSED,
+    E52BalanceUnavailable => ERR52_BALANCE_UNAVAILABLE,
+    E53LiabilityNotSeeded => ERR53_LIABILITY_NOT_SEEDED,
+    E54LiabilitySeeded => ERR54_LIABILITY_SEEDED,
+    E55ExceedSurplus => ERR55_EXCEED_SURPLUS,
+    E56LiabilityUnderflow => ERR56_LIABILITY_UNDERFLOW,
+    E57RetrievalTooEarly => ERR57_RETRIEVAL_TOO_EARLY,
+    E60DecimalIllegal => ERR60_DECIMAL_ILLEGAL,
+    E61AmpIllegal => ERR61_AMP_ILLEGAL,
+    E62FeeIllegal => ERR62_FEE_ILLEGAL,
+    E63MissingToken => ERR63_MISSING_TOKEN,
+    E64TokensCountIllegal => ERR64_TOKENS_COUNT_ILLEGAL,
+    E65InitTokenBalance => ERR65_INIT_TOKEN_BALANCE,
+    E66InvariantCalcErr => ERR66_INVARIANT_CALC_ERR,
+    E67LpshareCalcErr => ERR67_LPSHARE_CALC_ERR,
+    E68Slippage => ERR68_SLIPPAGE,
+    E69MinReserve => ERR69_MIN_RESERVE,
+    E70SwapOutCalcErr => ERR70_SWAP_OUT_CALC_ERR,
+    E71SwapDupTokens => ERR71_SWAP_DUP_TOKENS,
+    E72BondedPoolIllegal => ERR72_BONDED_POOL_ILLEGAL,
+    E73InsufficientBond => ERR73_INSUFFICIENT_BOND,
+    E74PoolNotActive => ERR74_POOL_NOT_ACTIVE,
+    E75NoPoolBond => ERR75_NO_POOL_BOND,
+    E76IllegalBondState => ERR76_ILLEGAL_BOND_STATE,
+    E77TokenDecimalsMismatch => ERR77_TOKEN_DECIMALS_MISMATCH,
+    E78NoTokenMigration => ERR78_NO_TOKEN_MIGRATION,
+    E79TokenMigrationNotReady => ERR79_TOKEN_MIGRATION_NOT_READY,
+    E80IllegalTokenMigration => ERR80_ILLEGAL_TOKEN_MIGRATION,
+    E81AmpInLock => ERR81_AMP_IN_LOCK,
+    E82InsufficientRampTime => ERR82_INSUFFICIENT_RAMP_TIME,
+    E83InvalidAmpFactor => ERR83_INVALID_AMP_FACTOR,
+    E84AmpLargeChange => ERR84_AMP_LARGE_CHANGE,
+    E85IllegalStatsWindow => ERR85_ILLEGAL_STATS_WINDOW,
+    E86PoolNotFound => ERR86_POOL_NOT_FOUND,
+    E87EmptyPool => ERR87_EMPTY_POOL,
+    E88NotSupported => ERR88_NOT_SUPPORTED,
+    E89IllegalLiquidityMigration => ERR89_ILLEGAL_LIQUIDITY_MIGRATION,
+    E90DelegationSlippageExceeded => ERR90_DELEGATION_SLIPPAGE_EXCEEDED,
+    E91NoDelegation => ERR91_NO_DELEGATION,
+    E92TokenNotDelegated => ERR92_TOKEN_NOT_DELEGATED,
+    E93DelegationCapExceeded => ERR93_DELEGATION_CAP_EXCEEDED,
+    E94IllegalDelegation => ERR94_ILLEGAL_DELEGATION,
+    E95OracleNotEnabled => ERR95_ORACLE_NOT_ENABLED,
+    E96IllegalOracleCapacity => ERR96_ILLEGAL_ORACLE_CAPACITY,
+    E97OracleHistoryTooShort => ERR97_ORACLE_HISTORY_TOO_SHORT,
+    E98TwapUnavailable => ERR98_TWAP_UNAVAILABLE,
+    E100NotAllowed => ERR100_NOT_ALLOWED,
+}
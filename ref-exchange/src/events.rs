@@ -0,0 +1,233 @@
+//! Structured events following NEP-297, emitted as `EVENT_JSON:` logs.
+//!
+//! Every state change an indexer cares about is reported here with typed fields,
+//! so log consumers don't depend on the wording of free-text logs.
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+use near_sdk::{log, AccountId};
+
+use crate::bonded_pool::PoolBondState;
+use crate::RunningState;
+
+pub const EVENT_STANDARD: &str = "ref-exchange";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// Tokens swapped in a pool on behalf of an account.
+    Swap {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        token_in: &'a AccountId,
+        amount_in: U128,
+        token_out: &'a AccountId,
+        amount_out: U128,
+        /// Token the fees are charged in, token_in for simple pools and token_out for stable pools.
+        fee_token_id: &'a AccountId,
+        total_fee: U128,
+        /// Exchange and referral fees, minted as LP shares.
+        admin_fee: U128,
+        /// Fee left to liquidity providers.
+        lp_fee: U128,
+    },
+    /// Liquidity added to a pool, amounts are the ones actually taken by the pool.
+    AddLiquidity {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        shares: U128,
+    },
+    /// Liquidity removed from a pool, amounts are the ones given back to the account.
+    RemoveLiquidity {
+        account_id: &'a AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        shares: U128,
+    },
+    /// LP shares moved between accounts.
+    LpTransfer {
+        pool_id: u64,
+        sender_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        amount: U128,
+        memo: Option<&'a str>,
+    },
+    /// Inner account balance moved between accounts.
+    Transfer {
+        token_id: &'a AccountId,
+        sender_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        amount: U128,
+        memo: Option<&'a str>,
+    },
+    /// Tokens deposited into an inner account.
+    Deposit {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Outcome of sending tokens out of the exchange.
+    /// When `success` is false, the amount was credited back.
+    Withdraw {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+        success: bool,
+    },
+    /// Tokens that failed to return to account kept in lost-found.
+    Lostfound {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Account claimed its lost-found tokens into its inner account.
+    LostfoundClaimed {
+        account_id: &'a AccountId,
+        token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// LP shares minted to the exchange as admin fee.
+    AdminFeeMinted {
+        pool_id: u64,
+        account_id: &'a AccountId,
+        shares: U128,
+    },
+    /// LP shares minted to a referral as referral fee.
+    ReferralFeeMinted {
+        pool_id: u64,
+        referral_id: &'a AccountId,
+        shares: U128,
+    },
+    /// LP shares earned by a pool creator moved from the exchange to the creator.
+    CreatorFeeClaimed {
+        pool_id: u64,
+        creator_id: &'a AccountId,
+        shares: U128,
+    },
+    /// Exchange fee shares of a pool burnt by a harvest, `amounts` are the ones removed in pool token order,
+    /// and the treasury received `received_amounts` of `received_tokens` for each of them.
+    FeeHarvested {
+        pool_id: u64,
+        treasury_id: &'a AccountId,
+        shares: U128,
+        amounts: Vec<U128>,
+        received_tokens: Vec<&'a AccountId>,
+        received_amounts: Vec<U128>,
+    },
+    /// Allowance of an internal token set by the owner, zero when revoked.
+    MftApproval {
+        token_id: &'a str,
+        owner_id: &'a AccountId,
+        spender_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Grantor allowed delegate to swap on its inner account.
+    TradingDelegationGranted {
+        grantor_id: &'a AccountId,
+        delegate_id: &'a AccountId,
+        token_caps: Vec<(&'a AccountId, U128)>,
+        period_sec: u32,
+    },
+    /// Grantor removed the permission of delegate.
+    TradingDelegationRevoked {
+        grantor_id: &'a AccountId,
+        delegate_id: &'a AccountId,
+    },
+    /// Price oracle of a pool enabled or its capacity grown.
+    PoolOracleCapacity {
+        pool_id: u64,
+        account_id: &'a AccountId,
+        capacity: u32,
+    },
+    /// New pool added to the exchange.
+    PoolCreated {
+        pool_id: u64,
+        pool_kind: String,
+        token_account_ids: &'a [AccountId],
+        total_fee: u32,
+    },
+    /// Bond of a pool created without owner's permission changed state.
+    /// Amount is the bond held, or the one that left the contract on release or rejection.
+    PoolBond {
+        pool_id: u64,
+        creator_id: &'a AccountId,
+        token_id: Option<&'a AccountId>,
+        amount: U128,
+        state: PoolBondState,
+    },
+    /// Token of a pool replaced by its new contract account, amount is the pool's reserve.
+    PoolTokenMigrated {
+        pool_id: u64,
+        old_token_id: &'a AccountId,
+        new_token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Balance of an inner account moved to the new contract account of the token.
+    AccountTokenMigrated {
+        account_id: &'a AccountId,
+        old_token_id: &'a AccountId,
+        new_token_id: &'a AccountId,
+        amount: U128,
+    },
+    /// Running state of the contract changed.
+    StateChanged {
+        old_state: &'a RunningState,
+        new_state: &'a RunningState,
+        changed_by: &'a AccountId,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+impl Event<'_> {
+    /// Logs the event as `EVENT_JSON:{...}`.
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_STANDARD_VERSION,
+            event: self,
+        };
+        log!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&log).expect("ERR_SERIALIZE_EVENT")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+
+    #[test]
+    fn test_event_format() {
+        testing_env!(VMContextBuilder::new().build());
+        Event::Deposit {
+            account_id: accounts(0).as_ref(),
+            token_id: accounts(1).as_ref(),
+            amount: U128(100),
+        }
+        .emit();
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"ref-exchange","version":"1.0.0","event":"deposit","data":{"account_id":"alice","token_id":"bob","amount":"100"}}"#
+                    .to_string()
+            ]
+        );
+    }
+}
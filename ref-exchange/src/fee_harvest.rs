@@ -0,0 +1,201 @@
+//! Harvest of the exchange fee into a treasury.
+//!
+//! The exchange fee accrues as LP shares of the exchange account in each pool.
+//! Anyone can harvest a range of pools: the exchange's shares, except the ones reserved
+//! for pool creators, are burnt, and the tokens removed are credited to the treasury's
+//! inner account, or its lost-found if the account can't take them.
+//! When a treasury token is set, each token with a swap pool configured is first swapped
+//! into it through that pool, as long as the output stays within the slippage bound from
+//! the pool's TWAP. Tokens that can't be swapped are credited as they are.
+
+use std::collections::HashMap;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::utils::FEE_DIVISOR;
+use crate::*;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct FeeHarvestConfig {
+    /// Account credited with the harvested tokens.
+    pub treasury_id: AccountId,
+    /// Token the harvested tokens are swapped into, None to credit them as they are.
+    pub treasury_token_id: Option<AccountId>,
+    /// Pool used to swap each token into the treasury token.
+    pub swap_pools: HashMap<AccountId, u64>,
+    /// Max shortfall of a swap's output from the amount at the pool's TWAP, in bps.
+    pub max_slippage_bps: u32,
+    /// Window of the TWAP swaps are checked against.
+    pub twap_window_sec: u32,
+}
+
+impl Contract {
+    /// Burns the exchange's unreserved shares of given pool and credits the treasury.
+    fn internal_harvest_pool(&mut self, pool_id: u64, config: &FeeHarvestConfig) {
+        let exchange_id = env::current_account_id();
+        let mut pool = self.pools.get(pool_id).unwrap();
+        let shares = pool
+            .share_balances(&exchange_id)
+            .saturating_sub(self.internal_creator_reserved_shares(pool_id));
+        if shares == 0 {
+            return;
+        }
+        if let Err(err) = pool.try_predict_remove_liquidity(shares) {
+            env::log(format!("Pool {} not harvested: {}", pool_id, err.message()).as_bytes());
+            return;
+        }
+        let tokens = pool.tokens().to_vec();
+        let amounts = pool.remove_liquidity(&exchange_id, shares, vec![0; tokens.len()]);
+        self.pools.replace(pool_id, &pool);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::RemoveLiquidity {
+            account_id: &exchange_id,
+            pool_id,
+            amounts: amounts.iter().map(|amount| U128(*amount)).collect(),
+            shares: U128(shares),
+        }
+        .emit();
+
+        let mut received_tokens = vec![];
+        let mut received_amounts = vec![];
+        for (token_id, amount) in tokens.iter().zip(amounts.iter()) {
+            let (received_token, received_amount) = match self.internal_harvest_swap(config, token_id, *amount) {
+                Some(amount_out) => (config.treasury_token_id.as_ref().unwrap(), amount_out),
+                None => (token_id, *amount),
+            };
+            if received_amount > 0 {
+                self.internal_credit_treasury(&config.treasury_id, received_token, received_amount);
+            }
+            received_tokens.push(received_token);
+            received_amounts.push(U128(received_amount));
+        }
+        Event::FeeHarvested {
+            pool_id,
+            treasury_id: &config.treasury_id,
+            shares: U128(shares),
+            amounts: amounts.into_iter().map(U128).collect(),
+            received_tokens,
+            received_amounts,
+        }
+        .emit();
+    }
+
+    /// Swaps harvested amount of given token into the treasury token, returns the amount received.
+    /// Returns None if the token is not to be swapped, or the swap is not possible within the slippage bound.
+    fn internal_harvest_swap(
+        &mut self,
+        config: &FeeHarvestConfig,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> Option<Balance> {
+        let treasury_token_id = config.treasury_token_id.as_ref()?;
+        if amount == 0 || token_id == treasury_token_id {
+            return None;
+        }
+        let pool_id = *config.swap_pools.get(token_id)?;
+        if !self.is_pool_active(pool_id) || self.denied_tokens.get(treasury_token_id).is_some() {
+            return None;
+        }
+        let min_amount_out = match self.internal_twap_min_amount_out(
+            pool_id,
+            token_id,
+            amount,
+            treasury_token_id,
+            config.twap_window_sec,
+            config.max_slippage_bps,
+        ) {
+            Ok(min_amount_out) => min_amount_out,
+            Err(err) => {
+                env::log(format!("Swap of {} in pool {} skipped: {}", token_id, pool_id, err).as_bytes());
+                return None;
+            }
+        };
+        let pool = self.pools.get(pool_id)?;
+        if let Err(err) = pool.check_swap(
+            token_id,
+            amount,
+            treasury_token_id,
+            min_amount_out,
+            &AdminFees::new(self.exchange_fee),
+        ) {
+            env::log(format!("Swap of {} in pool {} skipped: {}", token_id, pool_id, err).as_bytes());
+            return None;
+        }
+        let swap_action = SwapAction {
+            pool_id,
+            token_in: token_id.clone(),
+            amount_in: None,
+            token_out: treasury_token_id.clone(),
+            min_amount_out: U128(min_amount_out),
+        };
+        Some(self.internal_pool_swap(&env::current_account_id(), &swap_action, amount, &None))
+    }
+
+    /// Deposits into the treasury's inner account, or its lost-found if not registered or short of storage.
+    fn internal_credit_treasury(&mut self, treasury_id: &AccountId, token_id: &AccountId, amount: Balance) {
+        if let Some(mut account) = self.internal_get_account(treasury_id) {
+            if account.deposit_with_storage_check(token_id, amount) {
+                self.accounts.insert(treasury_id, &account.into());
+                return;
+            }
+        }
+        self.internal_lostfound(treasury_id, token_id, amount);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Harvests the exchange fee of up to `limit` pools from `from_index` into the treasury.
+    /// Anyone can call it. Returns index of the next pool, the number of pools once all are done.
+    pub fn harvest_exchange_fees(&mut self, from_index: u64, limit: u64) -> u64 {
+        self.assert_contract_running();
+        let config = self.fee_harvest_config.clone().expect(ERR39_NO_FEE_HARVEST_CONFIG);
+        let end = std::cmp::min(from_index.saturating_add(limit), self.pools.len());
+        for pool_id in from_index..end {
+            self.internal_harvest_pool(pool_id, &config);
+        }
+        end
+    }
+
+    /// Sets where harvested exchange fees go. Each swap pool must hold its token and the treasury token.
+    /// Only can be called by owner.
+    #[payable]
+    pub fn set_fee_harvest_config(&mut self, config: FeeHarvestConfig) {
+        assert_one_yocto();
+        self.assert_owner();
+        assert!(
+            env::is_valid_account_id(config.treasury_id.as_bytes())
+                && config.max_slippage_bps <= FEE_DIVISOR,
+            "{}",
+            ERR40_ILLEGAL_FEE_HARVEST_CONFIG
+        );
+        if !config.swap_pools.is_empty() {
+            let treasury_token_id = config
+                .treasury_token_id
+                .as_ref()
+                .expect(ERR40_ILLEGAL_FEE_HARVEST_CONFIG);
+            assert!(config.twap_window_sec > 0, "{}", ERR40_ILLEGAL_FEE_HARVEST_CONFIG);
+            for (token_id, pool_id) in config.swap_pools.iter() {
+                let pool = self.pools.get(*pool_id).expect("ERR_NO_POOL");
+                assert!(
+                    token_id != treasury_token_id
+                        && pool.tokens().contains(token_id)
+                        && pool.tokens().contains(treasury_token_id),
+                    "{}",
+                    ERR40_ILLEGAL_FEE_HARVEST_CONFIG
+                );
+            }
+        }
+        self.fee_harvest_config = Some(config);
+    }
+
+    /// Returns where harvested exchange fees go, None if not configured.
+    pub fn get_fee_harvest_config(&self) -> Option<FeeHarvestConfig> {
+        self.fee_harvest_config.clone()
+    }
+}
@@ -4,7 +4,7 @@ use near_sdk::collections::{UnorderedMap, Vector, LookupMap, UnorderedSet};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::{AccountId, Balance, StorageUsage, near_bindgen, PanicOnDefault};
 use crate::account_deposit::{Account, VAccount};
-use crate::StorageKey;
+use crate::{RunningState, StorageKey};
 use crate::pool::Pool;
 
 /// Account deposits information and storage cost.
@@ -47,3 +47,24 @@ pub struct ContractV1 {
     /// Set of whitelisted tokens by "owner".
     pub whitelisted_tokens: UnorderedSet<AccountId>,
 }
+
+/// Contract state of versions 1.1.0 to 1.4.2.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractV2 {
+    /// Account of the owner.
+    pub owner_id: AccountId,
+    /// Exchange fee, that goes to exchange itself (managed by governance).
+    pub exchange_fee: u32,
+    /// Referral fee, that goes to referrer in the call.
+    pub referral_fee: u32,
+    /// List of all the pools.
+    pub pools: Vector<Pool>,
+    /// Accounts registered, keeping track all the amounts deposited, storage and more.
+    pub accounts: LookupMap<AccountId, VAccount>,
+    /// Set of whitelisted tokens by "owner".
+    pub whitelisted_tokens: UnorderedSet<AccountId>,
+    /// Set of guardians.
+    pub guardians: UnorderedSet<AccountId>,
+    /// Running state
+    pub state: RunningState,
+}
@@ -0,0 +1,217 @@
+//! Per-token liability ledger: how much of each token the exchange owes in total,
+//! inner-account deposits plus pool reserves plus lost-found.
+//!
+//! The counter only moves when tokens cross the contract boundary:
+//! it increases when tokens are deposited and decreases once a transfer out is confirmed.
+//! Swaps, liquidity changes, fee minting, internal transfers and lost-found moves
+//! keep tokens inside the contract, so they are net zero and don't touch it.
+//! Comparing it with the contract's balance on the token gives the surplus
+//! that can be retrieved without touching users' funds.
+//!
+//! The balance also holds deposits in flight, moved to the contract but not yet counted
+//! by `ft_on_transfer`, so the surplus read at one time can be too high. Retrieval is two-step:
+//! owner requests an amount, and it is only sent if the surplus still covers it when requested
+//! again after `RETRIEVAL_DELAY_SEC`, by which time deposits in flight at the first read are counted.
+
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, near_bindgen, AccountId, Balance, Promise, PromiseOrValue, PromiseResult, Timestamp,
+};
+
+use crate::utils::{ext_self, GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER};
+use crate::*;
+
+/// Seconds between the request of a retrieval and the call that sends it.
+pub const RETRIEVAL_DELAY_SEC: u64 = 60;
+
+/// Retrieval of surplus requested by owner.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PendingRetrieval {
+    pub amount: Balance,
+    pub requested_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct SolvencyReport {
+    pub token_id: AccountId,
+    /// Balance of the contract on the token.
+    pub balance: U128,
+    /// Total amount owed to users.
+    pub liability: U128,
+    pub surplus: U128,
+    pub deficit: U128,
+    /// False if the token still misses its pre-upgrade liabilities, so the report can't be trusted.
+    pub complete: bool,
+}
+
+impl Contract {
+    pub(crate) fn internal_increase_liability(&mut self, token_id: &AccountId, amount: Balance) {
+        let liability = self.token_liabilities.get(token_id).unwrap_or(0);
+        self.token_liabilities.insert(token_id, &(liability + amount));
+    }
+
+    /// Saturates at zero on an upgraded contract, where a token seeded short may go out
+    /// more than it was counted in. On a contract that had the ledger since creation
+    /// it can't happen, so it fails. For synchronous calls only, callbacks use
+    /// `internal_decrease_liability_saturating`.
+    pub(crate) fn internal_decrease_liability(&mut self, token_id: &AccountId, amount: Balance) {
+        let liability = self.token_liabilities.get(token_id).unwrap_or(0);
+        assert!(
+            amount <= liability || self.liability_seeded_tokens.is_some(),
+            "{}",
+            ERR56_LIABILITY_UNDERFLOW
+        );
+        self.internal_decrease_liability_saturating(token_id, amount);
+    }
+
+    /// Decreases liability of given token, logging and saturating at zero if it's short.
+    /// Used in callbacks, where failing would leave the transfer result half-applied.
+    pub(crate) fn internal_decrease_liability_saturating(&mut self, token_id: &AccountId, amount: Balance) {
+        let liability = self.token_liabilities.get(token_id).unwrap_or(0);
+        if amount > liability {
+            env::log(
+                format!(
+                    "Liability of {} is {}, short of {} going out.",
+                    token_id, liability, amount
+                )
+                .as_bytes(),
+            );
+        }
+        self.token_liabilities
+            .insert(token_id, &liability.saturating_sub(amount));
+    }
+
+    /// Whether liability of given token covers everything owed, including pre-upgrade balances.
+    pub(crate) fn is_liability_complete(&self, token_id: &AccountId) -> bool {
+        self.liability_seeded_tokens
+            .as_ref()
+            .map(|seeded| seeded.contains(token_id))
+            .unwrap_or(true)
+    }
+
+    fn internal_solvency_report(&self, token_id: AccountId) -> SolvencyReport {
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value)
+                    .expect(ERR52_BALANCE_UNAVAILABLE)
+                    .0
+            }
+            _ => env::panic(ERR52_BALANCE_UNAVAILABLE.as_bytes()),
+        };
+        let liability = self.token_liabilities.get(&token_id).unwrap_or(0);
+        SolvencyReport {
+            complete: self.is_liability_complete(&token_id),
+            token_id,
+            balance: U128(balance),
+            liability: U128(liability),
+            surplus: U128(balance.saturating_sub(liability)),
+            deficit: U128(liability.saturating_sub(balance)),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns total amount of given token owed by the exchange.
+    pub fn get_token_liability(&self, token_id: ValidAccountId) -> U128 {
+        U128(self.token_liabilities.get(token_id.as_ref()).unwrap_or(0))
+    }
+
+    /// Adds liabilities that existed before the ledger was introduced. Only can be called by owner.
+    /// Each token can be seeded once, and only on a contract upgraded from a version without the ledger.
+    #[payable]
+    pub fn seed_token_liabilities(&mut self, liabilities: Vec<(ValidAccountId, U128)>) {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut seeded = self
+            .liability_seeded_tokens
+            .take()
+            .expect(ERR54_LIABILITY_SEEDED);
+        for (token_id, amount) in liabilities {
+            assert!(seeded.insert(token_id.as_ref()), "{}", ERR54_LIABILITY_SEEDED);
+            self.internal_increase_liability(token_id.as_ref(), amount.0);
+        }
+        self.liability_seeded_tokens = Some(seeded);
+    }
+
+    /// Compares the contract's balance on given token with its liability.
+    /// Returns promise resolving into `SolvencyReport`.
+    pub fn solvency_report(&self, token_id: ValidAccountId) -> Promise {
+        ext_fungible_token::ft_balance_of(
+            env::current_account_id(),
+            token_id.as_ref(),
+            0,
+            GAS_FOR_BASIC_OP,
+        )
+        .then(ext_self::exchange_callback_solvency_report(
+            token_id.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_BASIC_OP,
+        ))
+    }
+
+    #[private]
+    pub fn exchange_callback_solvency_report(&self, token_id: AccountId) -> SolvencyReport {
+        self.internal_solvency_report(token_id)
+    }
+
+    /// Requires the contract's balance to cover `amount` of given token on top of liability.
+    /// Sends it to owner if the same amount was requested at least `RETRIEVAL_DELAY_SEC` ago,
+    /// otherwise records the request, replacing any previous one of the token.
+    #[private]
+    pub fn exchange_callback_retrieve_unmanaged_token(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+    ) -> PromiseOrValue<()> {
+        let report = self.internal_solvency_report(token_id.clone());
+        assert!(report.complete, "{}", ERR53_LIABILITY_NOT_SEEDED);
+        assert!(amount.0 <= report.surplus.0, "{}", ERR55_EXCEED_SURPLUS);
+        let now = env::block_timestamp();
+        match self.pending_retrievals.get(&token_id) {
+            Some(pending) if pending.amount == amount.0 => {
+                assert!(
+                    now >= pending.requested_at + RETRIEVAL_DELAY_SEC * 1_000_000_000,
+                    "{}",
+                    ERR57_RETRIEVAL_TOO_EARLY
+                );
+                self.pending_retrievals.remove(&token_id);
+                env::log(
+                    format!(
+                        "Going to retrieve token {} to owner, amount: {}",
+                        &token_id, amount.0
+                    )
+                    .as_bytes(),
+                );
+                PromiseOrValue::Promise(ext_fungible_token::ft_transfer(
+                    self.owner_id.clone(),
+                    amount,
+                    None,
+                    &token_id,
+                    1,
+                    GAS_FOR_FT_TRANSFER,
+                ))
+            }
+            _ => {
+                self.pending_retrievals.insert(
+                    &token_id,
+                    &PendingRetrieval { amount: amount.0, requested_at: now },
+                );
+                env::log(
+                    format!(
+                        "Retrieval of token {} requested, amount: {}, call again after {} seconds",
+                        &token_id, amount.0, RETRIEVAL_DELAY_SEC
+                    )
+                    .as_bytes(),
+                );
+                PromiseOrValue::Value(())
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
 
@@ -6,7 +7,7 @@ use near_contract_standards::storage_management::{
 };
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedSet, Vector};
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{
     assert_one_yocto, env, log, near_bindgen, AccountId, Balance, PanicOnDefault, Promise,
@@ -18,9 +19,19 @@ pub use crate::action::SwapAction;
 use crate::action::{Action, ActionResult};
 use crate::errors::*;
 use crate::admin_fee::AdminFees;
+use crate::bonded_pool::{PoolBond, PoolBondConfig};
+use crate::delegation::TradingDelegation;
+use crate::events::Event;
+use crate::fee_harvest::FeeHarvestConfig;
+use crate::liability::PendingRetrieval;
+use crate::oracle::PoolOracle;
 use crate::pool::Pool;
+use crate::pool_creator::PoolCreator;
 use crate::simple_pool::SimplePool;
 use crate::stable_swap::StableSwapPool;
+use crate::stats::StatsBucket;
+use crate::token_metadata::TokenMetadata;
+use crate::token_migration::TokenMigration;
 use crate::utils::check_token_duplicates;
 pub use crate::views::{PoolInfo, ContractMetadata};
 
@@ -28,13 +39,26 @@ mod account_deposit;
 mod action;
 mod errors;
 mod admin_fee;
+mod bonded_pool;
+mod delegation;
+mod events;
+mod fee_harvest;
 mod legacy;
+mod liability;
 mod multi_fungible_token;
+mod oracle;
 mod owner;
 mod pool;
+mod pool_creator;
+mod portfolio;
+mod quote;
 mod simple_pool;
+mod simulation;
 mod stable_swap;
+mod stats;
 mod storage_impl;
+mod token_metadata;
+mod token_migration;
 mod token_receiver;
 mod utils;
 mod views;
@@ -49,6 +73,22 @@ pub(crate) enum StorageKey {
     Whitelist,
     Guardian,
     AccountTokens {account_id: AccountId},
+    MftAllowances,
+    TradingDelegations,
+    LostFound,
+    TokenLiabilities,
+    LiabilitySeededTokens,
+    AccountPools,
+    PoolOracles,
+    PoolObservations { pool_id: u64 },
+    PoolStats,
+    PoolBonds,
+    TokenMetadata,
+    DeniedTokens,
+    TokenMigrations,
+    PoolCreators,
+    PendingRetrievals,
+    DeactivatedPools,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -86,6 +126,44 @@ pub struct Contract {
     guardians: UnorderedSet<AccountId>,
     /// Running state
     state: RunningState,
+    /// Allowances of internal tokens (LP shares or inner account tokens),
+    /// keyed by (token_id, owner_id, spender_id).
+    mft_allowances: LookupMap<(String, AccountId, AccountId), Balance>,
+    /// Swap permissions on inner accounts, keyed by grantor then delegate.
+    trading_delegations: LookupMap<AccountId, HashMap<AccountId, TradingDelegation>>,
+    /// Tokens that failed to return to their owner, claimable by that account.
+    lostfound: UnorderedMap<AccountId, HashMap<AccountId, Balance>>,
+    /// Total amount of each token owed by the exchange.
+    token_liabilities: LookupMap<AccountId, Balance>,
+    /// Tokens whose pre-upgrade liabilities were seeded by owner.
+    /// None if the ledger exists since contract creation, so all tokens are complete.
+    liability_seeded_tokens: Option<LookupSet<AccountId>>,
+    /// Sorted ids of pools where each account has a share entry.
+    account_pools: LookupMap<AccountId, Vec<u64>>,
+    /// Price oracles of the pools that have one enabled.
+    pool_oracles: LookupMap<u64, PoolOracle>,
+    /// Hourly swap statistics, keyed by pool id and hour of the week.
+    pool_stats: LookupMap<(u64, u32), StatsBucket>,
+    /// Bond required to create a pool without owner's permission.
+    pool_bond_config: PoolBondConfig,
+    /// Bonds of pools created without owner's permission, until released.
+    pool_bonds: LookupMap<u64, PoolBond>,
+    /// Metadata of tokens read from their contracts.
+    token_metadata: LookupMap<AccountId, TokenMetadata>,
+    /// Tokens denied by guardians with the reason, blocked from deposits, swaps into them and new pools.
+    denied_tokens: UnorderedMap<AccountId, String>,
+    /// Migrations of tokens to new contract accounts, keyed by the old account.
+    token_migrations: LookupMap<AccountId, TokenMigration>,
+    /// Creators of the pools and their part of the exchange fee.
+    pool_creators: LookupMap<u64, PoolCreator>,
+    /// Max part of the exchange fee pool creators can take, in bps.
+    creator_fee_cap: u32,
+    /// Where harvested exchange fees go, None until set by owner.
+    fee_harvest_config: Option<FeeHarvestConfig>,
+    /// Retrievals of unmanaged tokens requested by owner, keyed by token.
+    pending_retrievals: LookupMap<AccountId, PendingRetrieval>,
+    /// Stable pools closed to swaps as their decimals differ from the token metadata read after creation.
+    deactivated_pools: UnorderedSet<u64>,
 }
 
 #[near_bindgen]
@@ -101,6 +179,24 @@ impl Contract {
             whitelisted_tokens: UnorderedSet::new(StorageKey::Whitelist),
             guardians: UnorderedSet::new(StorageKey::Guardian),
             state: RunningState::Running,
+            mft_allowances: LookupMap::new(StorageKey::MftAllowances),
+            trading_delegations: LookupMap::new(StorageKey::TradingDelegations),
+            lostfound: UnorderedMap::new(StorageKey::LostFound),
+            token_liabilities: LookupMap::new(StorageKey::TokenLiabilities),
+            liability_seeded_tokens: None,
+            account_pools: LookupMap::new(StorageKey::AccountPools),
+            pool_oracles: LookupMap::new(StorageKey::PoolOracles),
+            pool_stats: LookupMap::new(StorageKey::PoolStats),
+            pool_bond_config: PoolBondConfig::default(),
+            pool_bonds: LookupMap::new(StorageKey::PoolBonds),
+            token_metadata: LookupMap::new(StorageKey::TokenMetadata),
+            denied_tokens: UnorderedMap::new(StorageKey::DeniedTokens),
+            token_migrations: LookupMap::new(StorageKey::TokenMigrations),
+            pool_creators: LookupMap::new(StorageKey::PoolCreators),
+            creator_fee_cap: 0,
+            fee_harvest_config: None,
+            pending_retrievals: LookupMap::new(StorageKey::PendingRetrievals),
+            deactivated_pools: UnorderedSet::new(StorageKey::DeactivatedPools),
         }
     }
 
@@ -110,13 +206,10 @@ impl Contract {
     pub fn add_simple_pool(&mut self, tokens: Vec<ValidAccountId>, fee: u32) -> u64 {
         self.assert_contract_running();
         check_token_duplicates(&tokens);
-        self.internal_add_pool(Pool::SimplePool(SimplePool::new(
-            self.pools.len() as u32,
-            tokens,
-            fee,
-            0,
-            0,
-        )))
+        self.internal_add_pool(
+            Pool::SimplePool(SimplePool::new(self.pools.len() as u32, tokens, fee, 0, 0)),
+            Some(env::predecessor_account_id()),
+        )
     }
 
     /// Adds new "Stable Pool" with given tokens, decimals, fee and amp.
@@ -125,6 +218,8 @@ impl Contract {
     /// decimals: each pool tokens decimal, needed to make them comparable.
     /// fee: total fee of the pool, admin fee is inclusive.
     /// amp_factor: algorithm parameter, decide how stable the pool will be.
+    /// Decimals must agree with the token metadata registry, for tokens already in it.
+    /// The metadata of other tokens is fetched, and the pool is deactivated if it doesn't agree.
     #[payable]
     pub fn add_stable_swap_pool(
         &mut self,
@@ -135,13 +230,20 @@ impl Contract {
     ) -> u64 {
         assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
         check_token_duplicates(&tokens);
-        self.internal_add_pool(Pool::StableSwapPool(StableSwapPool::new(
-            self.pools.len() as u32,
-            tokens,
-            decimals,
-            amp_factor as u128,
-            fee,
-        )))
+        self.assert_token_decimals(&tokens, &decimals);
+        // Created by owner or guardians, so it has no creator taking a fee share.
+        let pool_id = self.internal_add_pool(
+            Pool::StableSwapPool(StableSwapPool::new(
+                self.pools.len() as u32,
+                tokens.clone(),
+                decimals,
+                amp_factor as u128,
+                fee,
+            )),
+            None,
+        );
+        self.internal_check_missing_token_decimals(pool_id, tokens.into_iter().map(|token_id| token_id.into()).collect());
+        pool_id
     }
 
     /// [AUDIT_03_reject(NOPE action is allowed by design)]
@@ -160,21 +262,18 @@ impl Contract {
         let mut account = self.internal_unwrap_account(&sender_id);
         // Validate that all tokens are whitelisted if no deposit (e.g. trade with access key).
         if env::attached_deposit() == 0 {
-            for action in &actions {
-                for token in action.tokens() {
-                    assert!(
-                        account.get_balance(&token).is_some() 
-                            || self.whitelisted_tokens.contains(&token),
-                        "{}",
-                        // [AUDIT_05]
-                        ERR27_DEPOSIT_NEEDED
-                    );
-                }
+            if let Err((_, err)) = self.check_action_tokens(&account, &actions) {
+                env::panic(err.as_bytes());
             }
         }
         let referral_id = referral_id.map(|r| r.into());
-        let result =
-            self.internal_execute_actions(&mut account, &referral_id, &actions, ActionResult::None);
+        let result = self.internal_execute_actions(
+            &sender_id,
+            &mut account,
+            &referral_id,
+            &actions,
+            ActionResult::None,
+        );
         self.internal_save_account(&sender_id, account);
         result
     }
@@ -211,30 +310,12 @@ impl Contract {
             env::attached_deposit() > 0,
             "Requires attached deposit of at least 1 yoctoNEAR"
         );
-        let prev_storage = env::storage_usage();
-        let sender_id = env::predecessor_account_id();
-        let mut amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        // Add amounts given to liquidity first. It will return the balanced amounts.
-        pool.add_liquidity(
-            &sender_id,
-            &mut amounts,
+        self.internal_add_liquidity(
+            &env::predecessor_account_id(),
+            pool_id,
+            amounts.into_iter().map(|amount| amount.into()).collect(),
+            min_amounts.map(|min_amounts| min_amounts.into_iter().map(|amount| amount.into()).collect()),
         );
-        if let Some(min_amounts) = min_amounts {
-            // Check that all amounts are above request min amounts in case of front running that changes the exchange rate.
-            for (amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
-                assert!(amount >= &min_amount.0, "ERR_MIN_AMOUNT");
-            }
-        }
-        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
-        let tokens = pool.tokens();
-        // Subtract updated amounts from deposits. This will fail if there is not enough funds for any of the tokens.
-        for i in 0..tokens.len() {
-            deposits.withdraw(&tokens[i], amounts[i]);
-        }
-        self.internal_save_account(&sender_id, deposits);
-        self.pools.replace(pool_id, &pool);
-        self.internal_check_storage(prev_storage);
     }
 
     /// For stable swap pool, user can add liquidity with token's combination as his will.
@@ -254,28 +335,43 @@ impl Contract {
             env::attached_deposit() > 0,
             "Requires attached deposit of at least 1 yoctoNEAR"
         );
-        let prev_storage = env::storage_usage();
-        let sender_id = env::predecessor_account_id();
-        let amounts: Vec<u128> = amounts.into_iter().map(|amount| amount.into()).collect();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        // Add amounts given to liquidity first. It will return the balanced amounts.
-        let mint_shares = pool.add_stable_liquidity(
-            &sender_id,
-            &amounts,
+        let amounts = amounts.into_iter().map(|amount| amount.into()).collect();
+        self.internal_add_stable_liquidity(
+            &env::predecessor_account_id(),
+            pool_id,
+            amounts,
             min_shares.into(),
-            AdminFees::new(self.exchange_fee),
-        );
-        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
-        let tokens = pool.tokens();
-        // Subtract amounts from deposits. This will fail if there is not enough funds for any of the tokens.
-        for i in 0..tokens.len() {
-            deposits.withdraw(&tokens[i], amounts[i]);
-        }
-        self.internal_save_account(&sender_id, deposits);
-        self.pools.replace(pool_id, &pool);
-        self.internal_check_storage(prev_storage);
+            false,
+        )
+        .into()
+    }
 
-        mint_shares.into()
+    /// Adds liquidity to a simple pool from a deposit of only one of its tokens.
+    /// Swaps the part of `amount` that balances the pool's ratio after the swap, fee included,
+    /// then adds both sides. What can't be added, a few units of rounding at most, stays in the inner account.
+    /// min_shares: Slippage, if shares minted are less than it, panic with ERR68_SLIPPAGE.
+    #[payable]
+    pub fn add_liquidity_single(
+        &mut self,
+        pool_id: u64,
+        token_id: ValidAccountId,
+        amount: U128,
+        min_shares: U128,
+    ) -> U128 {
+        self.assert_contract_running();
+        assert!(
+            env::attached_deposit() > 0,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        self.internal_add_liquidity_single(
+            &env::predecessor_account_id(),
+            pool_id,
+            token_id.as_ref(),
+            amount.into(),
+            min_shares.into(),
+            false,
+        )
+        .into()
     }
 
     /// Remove liquidity from the pool into general pool of liquidity.
@@ -283,29 +379,65 @@ impl Contract {
     pub fn remove_liquidity(&mut self, pool_id: u64, shares: U128, min_amounts: Vec<U128>) {
         assert_one_yocto();
         self.assert_contract_running();
-        let prev_storage = env::storage_usage();
+        self.internal_remove_liquidity(
+            &env::predecessor_account_id(),
+            pool_id,
+            shares.into(),
+            min_amounts.into_iter().map(|amount| amount.into()).collect(),
+        );
+    }
+
+    /// Moves liquidity to another pool of the same tokens, simple or stable.
+    /// Burns `shares` in `from_pool_id` and adds the amounts to `to_pool_id`,
+    /// what a simple pool doesn't take at its ratio stays in the inner account.
+    /// Attached NEAR should cover the LP registration in the new pool.
+    /// min_out_shares: Slippage, if shares minted are less than it, panic with ERR68_SLIPPAGE.
+    #[payable]
+    pub fn migrate_liquidity(
+        &mut self,
+        from_pool_id: u64,
+        to_pool_id: u64,
+        shares: U128,
+        min_out_shares: U128,
+    ) -> U128 {
+        self.assert_contract_running();
+        assert!(
+            env::attached_deposit() > 0,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
         let sender_id = env::predecessor_account_id();
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        let amounts = pool.remove_liquidity(
+        let from_tokens = self.pools.get(from_pool_id).expect("ERR_NO_POOL").tokens().to_vec();
+        let to_pool = self.pools.get(to_pool_id).expect("ERR_NO_POOL");
+        assert!(
+            from_pool_id != to_pool_id
+                && to_pool.tokens().len() == from_tokens.len()
+                && to_pool.tokens().iter().all(|token| from_tokens.contains(token)),
+            "{}",
+            ERR89_ILLEGAL_LIQUIDITY_MIGRATION
+        );
+        let removed = self.internal_remove_liquidity(
             &sender_id,
+            from_pool_id,
             shares.into(),
-            min_amounts
-                .into_iter()
-                .map(|amount| amount.into())
-                .collect(),
+            vec![0; from_tokens.len()],
         );
-        self.pools.replace(pool_id, &pool);
-        let tokens = pool.tokens();
-        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
-        for i in 0..tokens.len() {
-            deposits.deposit(&tokens[i], amounts[i]);
-        }
-        // Freed up storage balance from LP tokens will be returned to near_balance.
-        if prev_storage > env::storage_usage() {
-            deposits.near_amount +=
-                (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        let amounts: Vec<Balance> = to_pool
+            .tokens()
+            .iter()
+            .map(|token| removed[from_tokens.iter().position(|id| id == token).unwrap()])
+            .collect();
+        let min_out_shares: Balance = min_out_shares.into();
+        match to_pool {
+            Pool::SimplePool(_) => {
+                let (shares, _) = self.internal_add_liquidity(&sender_id, to_pool_id, amounts, None);
+                assert!(shares >= min_out_shares, "{}", ERR68_SLIPPAGE);
+                shares
+            }
+            Pool::StableSwapPool(_) => {
+                self.internal_add_stable_liquidity(&sender_id, to_pool_id, amounts, min_out_shares, false)
+            }
         }
-        self.internal_save_account(&sender_id, deposits);
+        .into()
     }
 
     /// For stable swap pool, LP can use it to remove liquidity with given token amount and distribution.
@@ -323,6 +455,8 @@ impl Contract {
         let prev_storage = env::storage_usage();
         let sender_id = env::predecessor_account_id();
         let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let fees = AdminFees::new(self.exchange_fee);
+        let prev_fee_shares = fees.receiver_shares(&pool);
         let burn_shares = pool.remove_liquidity_by_tokens(
             &sender_id,
             amounts
@@ -331,7 +465,7 @@ impl Contract {
                 .map(|amount| amount.into())
                 .collect(),
             max_burn_shares.into(),
-            AdminFees::new(self.exchange_fee),
+            &fees,
         );
         self.pools.replace(pool_id, &pool);
         let tokens = pool.tokens();
@@ -345,9 +479,71 @@ impl Contract {
                 (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
         }
         self.internal_save_account(&sender_id, deposits);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::RemoveLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            amounts,
+            shares: U128(burn_shares),
+        }
+        .emit();
+        self.internal_distribute_fee_shares(pool_id, &pool, &fees, prev_fee_shares);
 
         burn_shares.into()
     }
+
+    /// For stable swap pool, LP can use it to burn given shares for only one of the pool tokens.
+    /// pool_id: the stable swap pool id. If simple pool is given, panic with Unimplement.
+    /// shares: amount of shares to burn.
+    /// token_id: the token user want get back.
+    /// min_amount: This is slippage protection, if user would get less than it, panic with ERR68_SLIPPAGE
+    #[payable]
+    pub fn remove_liquidity_one_coin(
+        &mut self,
+        pool_id: u64,
+        shares: U128,
+        token_id: ValidAccountId,
+        min_amount: U128,
+    ) -> U128 {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let fees = AdminFees::new(self.exchange_fee);
+        let prev_fee_shares = fees.receiver_shares(&pool);
+        let amount = pool.remove_liquidity_one_coin(
+            &sender_id,
+            shares.into(),
+            token_id.as_ref(),
+            min_amount.into(),
+            &fees,
+        );
+        self.pools.replace(pool_id, &pool);
+        let mut deposits = self.internal_unwrap_or_default_account(&sender_id);
+        deposits.deposit(token_id.as_ref(), amount);
+        // Freed up storage balance from LP tokens will be returned to near_balance.
+        if prev_storage > env::storage_usage() {
+            deposits.near_amount +=
+                (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        }
+        self.internal_save_account(&sender_id, deposits);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::RemoveLiquidity {
+            account_id: &sender_id,
+            pool_id,
+            amounts: pool
+                .tokens()
+                .iter()
+                .map(|token| U128(if token == token_id.as_ref() { amount } else { 0 }))
+                .collect(),
+            shares,
+        }
+        .emit();
+        self.internal_distribute_fee_shares(pool_id, &pool, &fees, prev_fee_shares);
+
+        amount.into()
+    }
 }
 
 /// Internal methods implementation.
@@ -360,19 +556,59 @@ impl Contract {
         };
     }
 
+    fn assert_token_not_denied(&self, token_id: &AccountId) {
+        assert!(self.denied_tokens.get(token_id).is_none(), "{}", ERR16_TOKEN_DENIED);
+    }
+
+    /// Returns the error and index of the first action using a token neither registered
+    /// in the account nor whitelisted, checked by `execute_actions` without attached deposit.
+    fn check_action_tokens(&self, account: &Account, actions: &[Action]) -> Result<(), (usize, &'static str)> {
+        for (index, action) in actions.iter().enumerate() {
+            for token in action.tokens() {
+                if account.get_balance(&token).is_none() && !self.whitelisted_tokens.contains(&token) {
+                    // [AUDIT_05]
+                    return Err((index, ERR27_DEPOSIT_NEEDED));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the error a swap in given pool fails with before reaching the pool, if any.
+    fn check_pool_swap(&self, pool_id: u64, token_out: &AccountId) -> Result<(), &'static str> {
+        if pool_id >= self.pools.len() {
+            return Err("ERR_NO_POOL");
+        }
+        if self.deactivated_pools.contains(&pool_id) {
+            return Err(ERR77_TOKEN_DECIMALS_MISMATCH);
+        }
+        if !self.is_pool_active(pool_id) {
+            return Err(ERR74_POOL_NOT_ACTIVE);
+        }
+        if self.denied_tokens.get(token_out).is_some() {
+            return Err(ERR16_TOKEN_DENIED);
+        }
+        Ok(())
+    }
+
     /// Check how much storage taken costs and refund the left over back.
     fn internal_check_storage(&self, prev_storage: StorageUsage) {
+        self.internal_check_storage_with_budget(prev_storage, env::attached_deposit());
+    }
+
+    /// Same as `internal_check_storage`, paying from given budget out of the attached deposit.
+    fn internal_check_storage_with_budget(&self, prev_storage: StorageUsage, storage_budget: Balance) {
         let storage_cost = env::storage_usage()
             .checked_sub(prev_storage)
             .unwrap_or_default() as Balance
             * env::storage_byte_cost();
 
-        let refund = env::attached_deposit()
+        let refund = storage_budget
             .checked_sub(storage_cost)
             .expect(
                 format!(
                     "ERR_STORAGE_DEPOSIT need {}, attatched {}", 
-                    storage_cost, env::attached_deposit()
+                    storage_cost, storage_budget
                 ).as_str()
             );
         if refund > 0 {
@@ -380,23 +616,252 @@ impl Contract {
         }
     }
 
+    /// Same as `internal_check_storage`, paying from storage deposit of given account,
+    /// for calls without attached deposit. Fails if its available storage doesn't cover it.
+    fn internal_check_storage_from_account(&mut self, account_id: &AccountId, prev_storage: StorageUsage) {
+        let storage_cost = env::storage_usage().saturating_sub(prev_storage) as Balance
+            * env::storage_byte_cost();
+        if storage_cost > 0 {
+            let mut account = self.internal_unwrap_account(account_id);
+            assert!(account.storage_available() >= storage_cost, "{}", ERR11_INSUFFICIENT_STORAGE);
+            account.near_amount -= storage_cost;
+            self.internal_save_account(account_id, account);
+        }
+    }
+
     /// Adds given pool to the list and returns it's id.
     /// If there is not enough attached balance to cover storage, fails.
     /// If too much attached - refunds it back.
-    fn internal_add_pool(&mut self, mut pool: Pool) -> u64 {
-        let prev_storage = env::storage_usage();
+    fn internal_add_pool(&mut self, pool: Pool, creator_id: Option<AccountId>) -> u64 {
+        self.internal_add_pool_with_budget(pool, creator_id, env::storage_usage(), env::attached_deposit())
+    }
+
+    /// Adds given pool to the list and returns it's id, recording its creator if given.
+    /// Storage used since `prev_storage` is paid from `storage_budget` out of the attached deposit,
+    /// the rest of the budget is refunded.
+    pub(crate) fn internal_add_pool_with_budget(
+        &mut self,
+        mut pool: Pool,
+        creator_id: Option<AccountId>,
+        prev_storage: StorageUsage,
+        storage_budget: Balance,
+    ) -> u64 {
+        pool.tokens().iter().for_each(|token_id| self.assert_token_not_denied(token_id));
         let id = self.pools.len() as u64;
         // exchange share was registered at creation time
         pool.share_register(&env::current_account_id());
         self.pools.push(&pool);
-        self.internal_check_storage(prev_storage);
+        if let Some(creator_id) = creator_id {
+            self.internal_set_pool_creator(id, &creator_id);
+        }
+        self.internal_check_storage_with_budget(prev_storage, storage_budget);
+        self.internal_index_account_pool(&env::current_account_id(), id);
+        Event::PoolCreated {
+            pool_id: id,
+            pool_kind: pool.kind(),
+            token_account_ids: pool.tokens(),
+            total_fee: pool.get_fee(),
+        }
+        .emit();
         id
     }
 
+    /// Adds given amounts from sender's inner account to a simple pool, see `add_liquidity`.
+    /// Returns shares minted and the amounts taken, the rest stays in the inner account.
+    pub(crate) fn internal_add_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        pool_id: u64,
+        mut amounts: Vec<Balance>,
+        min_amounts: Option<Vec<Balance>>,
+    ) -> (Balance, Vec<Balance>) {
+        let prev_storage = env::storage_usage();
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        // Add amounts given to liquidity first. It will return the balanced amounts.
+        let shares = pool.add_liquidity(
+            sender_id,
+            &mut amounts,
+        );
+        if let Some(min_amounts) = min_amounts {
+            // Check that all amounts are above request min amounts in case of front running that changes the exchange rate.
+            for (amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
+                assert!(amount >= min_amount, "ERR_MIN_AMOUNT");
+            }
+        }
+        let mut deposits = self.internal_unwrap_or_default_account(sender_id);
+        let tokens = pool.tokens();
+        // Subtract updated amounts from deposits. This will fail if there is not enough funds for any of the tokens.
+        for i in 0..tokens.len() {
+            deposits.withdraw(&tokens[i], amounts[i]);
+        }
+        self.internal_save_account(sender_id, deposits);
+        self.pools.replace(pool_id, &pool);
+        self.internal_check_storage(prev_storage);
+        self.internal_index_account_pool(sender_id, pool_id);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::AddLiquidity {
+            account_id: sender_id,
+            pool_id,
+            amounts: amounts.iter().map(|amount| U128(*amount)).collect(),
+            shares: U128(shares),
+        }
+        .emit();
+        (shares, amounts)
+    }
+
+    /// Burns given shares of sender and credits the amounts to its inner account, see `remove_liquidity`.
+    pub(crate) fn internal_remove_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        pool_id: u64,
+        shares: Balance,
+        min_amounts: Vec<Balance>,
+    ) -> Vec<Balance> {
+        let prev_storage = env::storage_usage();
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let amounts = pool.remove_liquidity(sender_id, shares, min_amounts);
+        self.pools.replace(pool_id, &pool);
+        let tokens = pool.tokens();
+        let mut deposits = self.internal_unwrap_or_default_account(sender_id);
+        for i in 0..tokens.len() {
+            deposits.deposit(&tokens[i], amounts[i]);
+        }
+        // Freed up storage balance from LP tokens will be returned to near_balance.
+        if prev_storage > env::storage_usage() {
+            deposits.near_amount +=
+                (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        }
+        self.internal_save_account(sender_id, deposits);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::RemoveLiquidity {
+            account_id: sender_id,
+            pool_id,
+            amounts: amounts.iter().map(|amount| U128(*amount)).collect(),
+            shares: U128(shares),
+        }
+        .emit();
+        amounts
+    }
+
+    /// Adds given amounts from sender's inner account to a stable pool, see `add_stable_liquidity`.
+    /// Storage is paid from attached deposit, or from sender's storage deposit if `storage_from_deposit`.
+    pub(crate) fn internal_add_stable_liquidity(
+        &mut self,
+        sender_id: &AccountId,
+        pool_id: u64,
+        amounts: Vec<Balance>,
+        min_shares: Balance,
+        storage_from_deposit: bool,
+    ) -> Balance {
+        let prev_storage = env::storage_usage();
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let fees = AdminFees::new(self.exchange_fee);
+        let prev_fee_shares = fees.receiver_shares(&pool);
+        // Add amounts given to liquidity first. It will return the balanced amounts.
+        let mint_shares = pool.add_stable_liquidity(
+            sender_id,
+            &amounts,
+            min_shares,
+            &fees,
+        );
+        let mut deposits = self.internal_unwrap_or_default_account(sender_id);
+        let tokens = pool.tokens();
+        // Subtract amounts from deposits. This will fail if there is not enough funds for any of the tokens.
+        for i in 0..tokens.len() {
+            deposits.withdraw(&tokens[i], amounts[i]);
+        }
+        self.internal_save_account(sender_id, deposits);
+        self.pools.replace(pool_id, &pool);
+        if storage_from_deposit {
+            self.internal_check_storage_from_account(sender_id, prev_storage);
+        } else {
+            self.internal_check_storage(prev_storage);
+        }
+        self.internal_index_account_pool(sender_id, pool_id);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::AddLiquidity {
+            account_id: sender_id,
+            pool_id,
+            amounts: amounts.into_iter().map(U128).collect(),
+            shares: U128(mint_shares),
+        }
+        .emit();
+        self.internal_distribute_fee_shares(pool_id, &pool, &fees, prev_fee_shares);
+
+        mint_shares
+    }
+
+    /// Adds given amount of one token from sender's inner account to a simple pool, see `add_liquidity_single`.
+    /// Storage is paid from attached deposit, or from sender's storage deposit if `storage_from_deposit`.
+    pub(crate) fn internal_add_liquidity_single(
+        &mut self,
+        sender_id: &AccountId,
+        pool_id: u64,
+        token_in: &AccountId,
+        amount: Balance,
+        min_shares: Balance,
+        storage_from_deposit: bool,
+    ) -> Balance {
+        assert!(amount > 0, "{}", ERR31_ZERO_AMOUNT);
+        let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let tokens = pool.tokens().to_vec();
+        assert!(tokens.contains(token_in), "{}", ERR63_MISSING_TOKEN);
+        let swap_amount = pool.zap_swap_amount(token_in, amount);
+        let token_out = tokens.iter().find(|token| *token != token_in).unwrap().clone();
+        let mut deposits = self.internal_unwrap_or_default_account(sender_id);
+        deposits.withdraw(token_in, amount);
+        self.internal_save_account(sender_id, deposits);
+        // Inner account storage is covered by its own storage deposit, the swap and LP registration are charged.
+        let prev_storage = env::storage_usage();
+        let swap_action = SwapAction {
+            pool_id,
+            token_in: token_in.clone(),
+            amount_in: None,
+            token_out: token_out.clone(),
+            min_amount_out: U128(0),
+        };
+        let amount_out = self.internal_pool_swap(sender_id, &swap_action, swap_amount, &None);
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let mut amounts: Vec<Balance> = tokens
+            .iter()
+            .map(|token| if token == token_in { amount - swap_amount } else { amount_out })
+            .collect();
+        let offered = amounts.clone();
+        // Add both sides, it will return the balanced amounts.
+        let shares = pool.add_liquidity(sender_id, &mut amounts);
+        assert!(shares >= min_shares, "{}", ERR68_SLIPPAGE);
+        self.pools.replace(pool_id, &pool);
+        if storage_from_deposit {
+            self.internal_check_storage_from_account(sender_id, prev_storage);
+        } else {
+            self.internal_check_storage(prev_storage);
+        }
+        // Credit the part that was not added back.
+        let mut deposits = self.internal_unwrap_account(sender_id);
+        for ((token, offered), amount) in tokens.iter().zip(offered.iter()).zip(amounts.iter()) {
+            if offered > amount {
+                deposits.deposit(token, offered - amount);
+            }
+        }
+        self.internal_save_account(sender_id, deposits);
+        self.internal_index_account_pool(sender_id, pool_id);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::AddLiquidity {
+            account_id: sender_id,
+            pool_id,
+            amounts: amounts.into_iter().map(U128).collect(),
+            shares: U128(shares),
+        }
+        .emit();
+
+        shares
+    }
+
     /// Execute sequence of actions on given account. Modifies passed account.
     /// Returns result of the last action.
     fn internal_execute_actions(
         &mut self,
+        account_id: &AccountId,
         account: &mut Account,
         referral_id: &Option<AccountId>,
         actions: &[Action],
@@ -404,7 +869,7 @@ impl Contract {
     ) -> ActionResult {
         let mut result = prev_result;
         for action in actions {
-            result = self.internal_execute_action(account, referral_id, action, result);
+            result = self.internal_execute_action(account_id, account, referral_id, action, result);
         }
         result
     }
@@ -412,6 +877,7 @@ impl Contract {
     /// Executes single action on given account. Modifies passed account. Returns a result based on type of action.
     fn internal_execute_action(
         &mut self,
+        account_id: &AccountId,
         account: &mut Account,
         referral_id: &Option<AccountId>,
         action: &Action,
@@ -424,14 +890,7 @@ impl Contract {
                     .map(|value| value.0)
                     .unwrap_or_else(|| prev_result.to_amount());
                 account.withdraw(&swap_action.token_in, amount_in);
-                let amount_out = self.internal_pool_swap(
-                    swap_action.pool_id,
-                    &swap_action.token_in,
-                    amount_in,
-                    &swap_action.token_out,
-                    swap_action.min_amount_out.0,
-                    referral_id,
-                );
+                let amount_out = self.internal_pool_swap(account_id, swap_action, amount_in, referral_id);
                 account.deposit(&swap_action.token_out, amount_out);
                 // [AUDIT_02]
                 ActionResult::Amount(U128(amount_out))
@@ -439,31 +898,55 @@ impl Contract {
         }
     }
 
-    /// Swaps given amount_in of token_in into token_out via given pool.
+    /// Swaps given amount_in of the action's token_in into its token_out via its pool,
+    /// the action's own amount_in is not used.
     /// Should be at least min_amount_out or swap will fail (prevents front running and other slippage issues).
     fn internal_pool_swap(
         &mut self,
-        pool_id: u64,
-        token_in: &AccountId,
+        account_id: &AccountId,
+        swap_action: &SwapAction,
         amount_in: u128,
-        token_out: &AccountId,
-        min_amount_out: u128,
         referral_id: &Option<AccountId>,
     ) -> u128 {
-        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
-        let amount_out = pool.swap(
+        let pool_id = swap_action.pool_id;
+        let token_in = &swap_action.token_in;
+        let token_out = &swap_action.token_out;
+        if let Err(err) = self.check_pool_swap(pool_id, token_out) {
+            env::panic(err.as_bytes());
+        }
+        let mut pool = self.pools.get(pool_id).unwrap();
+        let fees = AdminFees {
+            exchange_fee: self.exchange_fee,
+            exchange_id: env::current_account_id(),
+            referral_fee: self.referral_fee,
+            referral_id: referral_id.clone(),
+        };
+        let prev_fee_shares = fees.receiver_shares(&pool);
+        let outcome = pool.swap(
             token_in,
             amount_in,
             token_out,
-            min_amount_out,
-            AdminFees {
-                exchange_fee: self.exchange_fee,
-                exchange_id: env::current_account_id(),
-                referral_fee: self.referral_fee,
-                referral_id: referral_id.clone(),
-            },
+            swap_action.min_amount_out.0,
+            &fees,
         );
+        let amount_out = outcome.amount_out;
         self.pools.replace(pool_id, &pool);
+        self.internal_update_oracle(pool_id, &pool);
+        self.internal_record_swap_stats(pool_id, &pool, token_in, amount_in, token_out, &outcome);
+        Event::Swap {
+            account_id,
+            pool_id,
+            token_in,
+            amount_in: U128(amount_in),
+            token_out,
+            amount_out: U128(amount_out),
+            fee_token_id: &pool.tokens()[outcome.fee_token],
+            total_fee: U128(outcome.total_fee),
+            admin_fee: U128(outcome.admin_fee),
+            lp_fee: U128(outcome.lp_fee),
+        }
+        .emit();
+        self.internal_distribute_fee_shares(pool_id, &pool, &fees, prev_fee_shares);
         amount_out
     }
 }
@@ -472,9 +955,10 @@ impl Contract {
 mod tests {
     use std::convert::TryFrom;
 
+    use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
     use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
     use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::{testing_env, Balance, MockedBlockchain};
+    use near_sdk::{testing_env, Balance, MockedBlockchain, PromiseOrValue};
     use near_sdk_sim::to_yocto;
 
     use super::*;
@@ -533,7 +1017,7 @@ mod tests {
         contract.extend_whitelisted_tokens(tokens.clone());
         testing_env!(context
             .predecessor_account_id(account_id.clone())
-            .attached_deposit(env::storage_byte_cost() * 300)
+            .attached_deposit(env::storage_byte_cost() * 400)
             .build());
         let pool_id = contract.add_simple_pool(tokens, 25);
         testing_env!(context
@@ -575,6 +1059,36 @@ mod tests {
             .0
     }
 
+    /// Records metadata of given tokens with given decimals, as read by `ft_metadata`.
+    fn record_token_metadata(context: &mut VMContextBuilder, contract: &mut Contract, tokens: Vec<(ValidAccountId, u8)>) {
+        for (token_id, decimals) in tokens {
+            testing_env!(
+                context.predecessor_account_id(accounts(0)).build(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                vec![ft_metadata_result(decimals)]
+            );
+            assert!(contract.exchange_callback_token_metadata(token_id.into()));
+        }
+    }
+
+    /// Result of `ft_metadata` call on a token with given decimals.
+    fn ft_metadata_result(decimals: u8) -> PromiseResult {
+        PromiseResult::Successful(
+            near_sdk::serde_json::to_vec(&FungibleTokenMetadata {
+                spec: "ft-1.0.0".to_string(),
+                name: "Token".to_string(),
+                symbol: "TKN".to_string(),
+                icon: None,
+                reference: None,
+                reference_hash: None,
+                decimals,
+            })
+            .unwrap(),
+        )
+    }
+
     #[test]
     fn test_basics() {
         let one_near = 10u128.pow(24);
@@ -661,6 +1175,8 @@ mod tests {
             accounts(1),
             contract.get_deposit(accounts(3), accounts(1)),
             None,
+            None,
+            None,
         );
         assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, 0);
     }
@@ -775,7 +1291,7 @@ mod tests {
             .predecessor_account_id(acc.clone())
             .attached_deposit(1)
             .build());
-        contract.withdraw(custom_token, U128(1_000), Some(true));
+        contract.withdraw(custom_token, U128(1_000), Some(true), None, None);
         let new = contract.storage_balance_of(acc.clone()).unwrap();
         // More available storage after withdrawing & unregistering the token.
         assert!(new.available.0 > prev.available.0);
@@ -903,8 +1419,54 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "E14: LP already registered")]
-    fn test_lpt_transfer() {
+    fn test_swap_events() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("1"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let amount_out = swap(&mut contract, 0, accounts(1), to_yocto("1"), accounts(2));
+        let logs = near_sdk::test_utils::get_logs();
+        assert_eq!(logs.len(), 2);
+        // 25 bps total fee, of which 1600 bps go to the exchange.
+        let total_fee = to_yocto("1") * 25 / 10_000;
+        let admin_fee = total_fee * 1_600 / 10_000;
+        assert_eq!(
+            logs[0],
+            format!(
+                r#"EVENT_JSON:{{"standard":"ref-exchange","version":"1.0.0","event":"swap","data":{{"account_id":"danny","pool_id":0,"token_in":"bob","amount_in":"{}","token_out":"charlie","amount_out":"{}","fee_token_id":"bob","total_fee":"{}","admin_fee":"{}","lp_fee":"{}"}}}}"#,
+                to_yocto("1"),
+                amount_out,
+                total_fee,
+                admin_fee,
+                total_fee - admin_fee
+            )
+        );
+        assert_eq!(
+            logs[1],
+            format!(
+                r#"EVENT_JSON:{{"standard":"ref-exchange","version":"1.0.0","event":"admin_fee_minted","data":{{"pool_id":0,"account_id":"{}","shares":"{}"}}}}"#,
+                env::current_account_id(),
+                contract.get_pool_shares(0, env::current_account_id().try_into().unwrap()).0
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E14: LP already registered")]
+    fn test_lpt_transfer() {
         // account(0) -- swap contract
         // account(1) -- token0 contract
         // account(2) -- token1 contract
@@ -1049,4 +1611,1663 @@ mod tests {
             .build());
         contract.mft_transfer(":0".to_string(), accounts(3), U128(to_yocto("1")), None);
     }
+
+    #[test]
+    fn test_mft_allowance() {
+        // account(3) -- lp owner
+        // account(4) -- spender
+        // account(5) -- receiver
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        let token_id = format!(":{}", pool_id);
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(to_yocto("0.00071"))
+            .build());
+        contract.mft_register(token_id.clone(), accounts(5));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.001"))
+            .build());
+        contract.mft_approve(token_id.clone(), accounts(4), U128(to_yocto("0.4")));
+        assert_eq!(
+            contract.mft_allowance(token_id.clone(), accounts(3), accounts(4)).0,
+            to_yocto("0.4")
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(1)
+            .build());
+        contract.mft_transfer_from(
+            token_id.clone(),
+            accounts(3),
+            accounts(5),
+            U128(to_yocto("0.3")),
+            None,
+        );
+        assert_eq!(
+            contract.mft_balance_of(token_id.clone(), accounts(5)).0,
+            to_yocto("0.3")
+        );
+        assert_eq!(
+            contract.mft_allowance(token_id.clone(), accounts(3), accounts(4)).0,
+            to_yocto("0.1")
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.mft_revoke(token_id.clone(), accounts(4));
+        assert_eq!(
+            contract.mft_allowance(token_id.clone(), accounts(3), accounts(4)).0,
+            0
+        );
+
+        // allowance spent in full is removed.
+        testing_env!(context.attached_deposit(to_yocto("0.001")).build());
+        contract.mft_approve(token_id.clone(), accounts(4), U128(to_yocto("0.1")));
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(1)
+            .build());
+        let prev_storage = env::storage_usage();
+        contract.mft_transfer_from(
+            token_id.clone(),
+            accounts(3),
+            accounts(5),
+            U128(to_yocto("0.1")),
+            None,
+        );
+        assert!(env::storage_usage() < prev_storage);
+        assert_eq!(
+            contract.mft_allowance(token_id, accounts(3), accounts(4)).0,
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E35: insufficient allowance")]
+    fn test_mft_transfer_from_over_allowance() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(5),
+            vec![(accounts(1), to_yocto("1"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.001"))
+            .build());
+        contract.mft_approve(accounts(1).to_string(), accounts(4), U128(to_yocto("1")));
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(1)
+            .build());
+        contract.mft_transfer_from(
+            accounts(1).to_string(),
+            accounts(3),
+            accounts(5),
+            U128(to_yocto("2")),
+            None,
+        );
+    }
+
+    fn setup_trading_delegation(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        max_slippage_bps: Option<u32>,
+    ) {
+        // account(3) -- grantor
+        // account(4) -- delegate
+        create_pool_with_liquidity(
+            context,
+            contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(context, contract, accounts(3), vec![(accounts(1), to_yocto("2"))]);
+        let mut token_caps = HashMap::new();
+        token_caps.insert(accounts(1).into(), U128(to_yocto("1")));
+        token_caps.insert(accounts(2).into(), U128(to_yocto("1")));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        contract.grant_trading_delegation(accounts(4), token_caps, 3600, max_slippage_bps, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(0)
+            .build());
+    }
+
+    #[test]
+    fn test_trading_delegation() {
+        let (mut context, mut contract) = setup_contract();
+        setup_trading_delegation(&mut context, &mut contract, None);
+        let amount_out = contract
+            .delegated_swap(
+                accounts(3),
+                vec![SwapAction {
+                    pool_id: 0,
+                    token_in: accounts(1).into(),
+                    amount_in: Some(U128(to_yocto("0.6"))),
+                    token_out: accounts(2).into(),
+                    min_amount_out: U128(1),
+                }],
+            )
+            .0;
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("1.4"));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(2)).0, amount_out);
+        let delegation = contract
+            .get_trading_delegation(accounts(3), accounts(4))
+            .unwrap();
+        assert_eq!(
+            delegation.quotas[accounts(1).as_ref()].remaining,
+            U128(to_yocto("0.4"))
+        );
+        assert_eq!(
+            delegation.quotas[accounts(2).as_ref()].remaining,
+            U128(to_yocto("1"))
+        );
+
+        // cap is restored in next period.
+        testing_env!(context.block_timestamp(3600 * 1_000_000_000).build());
+        let delegation = contract
+            .get_trading_delegation(accounts(3), accounts(4))
+            .unwrap();
+        assert_eq!(
+            delegation.quotas[accounts(1).as_ref()].remaining,
+            U128(to_yocto("1"))
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.revoke_trading_delegation(accounts(4));
+        assert!(contract.get_trading_delegations(accounts(3)).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "E93: delegation cap exceeded in this period")]
+    fn test_trading_delegation_over_cap() {
+        let (mut context, mut contract) = setup_contract();
+        setup_trading_delegation(&mut context, &mut contract, None);
+        for _ in 0..2 {
+            contract.delegated_swap(
+                accounts(3),
+                vec![SwapAction {
+                    pool_id: 0,
+                    token_in: accounts(1).into(),
+                    amount_in: Some(U128(to_yocto("0.6"))),
+                    token_out: accounts(2).into(),
+                    min_amount_out: U128(1),
+                }],
+            );
+        }
+    }
+
+    fn delegated_swap_with_oracle(amount_in: Balance) {
+        let (mut context, mut contract) = setup_contract();
+        let sec = 1_000_000_000;
+        testing_env!(context.block_timestamp(10 * sec).build());
+        setup_trading_delegation(&mut context, &mut contract, Some(100));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.set_pool_oracle_capacity(0, 2);
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .block_timestamp(110 * sec)
+            .attached_deposit(0)
+            .build());
+        contract.delegated_swap(
+            accounts(3),
+            vec![SwapAction {
+                pool_id: 0,
+                token_in: accounts(1).into(),
+                amount_in: Some(U128(amount_in)),
+                token_out: accounts(2).into(),
+                min_amount_out: U128(1),
+            }],
+        );
+    }
+
+    #[test]
+    fn test_trading_delegation_within_slippage() {
+        // Fee and price impact stay under the 1% allowed.
+        delegated_swap_with_oracle(to_yocto("0.01"));
+    }
+
+    #[test]
+    #[should_panic(expected = "E90: delegated swap exceeds max slippage from twap")]
+    fn test_trading_delegation_over_slippage() {
+        delegated_swap_with_oracle(to_yocto("0.6"));
+    }
+
+    #[test]
+    fn test_withdraw_batch() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_batch(
+            vec![(accounts(1), U128(to_yocto("1"))), (accounts(2), U128(0))],
+            None,
+        );
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("4"));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(2)).0, 0);
+
+        // second transfer fails and is credited back.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![]), PromiseResult::Failed]
+        );
+        let results = contract.exchange_callback_post_withdraw_batch(
+            vec![accounts(1).into(), accounts(2).into()],
+            accounts(3).into(),
+            vec![U128(to_yocto("1")), U128(to_yocto("10"))],
+        );
+        assert!(results[0].success);
+        assert!(!results[1].success);
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("4"));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(2)).0, to_yocto("10"));
+    }
+
+    #[test]
+    #[should_panic(expected = "E88: not supported by this pool kind")]
+    fn test_simple_pool_remove_liquidity_one_coin() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        contract.predict_remove_liquidity_one_coin(pool_id, U128(to_yocto("1")), accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "E42: too many tokens in withdraw batch")]
+    fn test_withdraw_batch_too_large() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![]);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let token_amounts = (0..=account_deposit::MAX_WITHDRAW_BATCH)
+            .map(|i| (ValidAccountId::try_from(format!("token{}", i)).unwrap(), U128(1)))
+            .collect();
+        contract.withdraw_batch(token_amounts, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E43: not enough gas for withdraw batch")]
+    fn test_withdraw_batch_gas() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .prepaid_gas(50_000_000_000_000)
+            .build());
+        contract.withdraw_batch(
+            vec![(accounts(1), U128(to_yocto("1"))), (accounts(2), U128(0))],
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E44: not enough gas for transfer call")]
+    fn test_withdraw_call_gas() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .prepaid_gas(40_000_000_000_000)
+            .build());
+        contract.withdraw(
+            accounts(1),
+            U128(to_yocto("2")),
+            None,
+            Some(accounts(4)),
+            Some("deposit".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_withdraw_call_refund_unused() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .prepaid_gas(300_000_000_000_000)
+            .build());
+        contract.withdraw(
+            accounts(1),
+            U128(to_yocto("2")),
+            None,
+            Some(accounts(4)),
+            Some("deposit".to_string()),
+        );
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("3"));
+
+        // receiver used only half of it.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(to_yocto("1"))).unwrap()
+            )]
+        );
+        contract.exchange_callback_post_withdraw_call(
+            accounts(1).into(),
+            accounts(3).into(),
+            U128(to_yocto("2")),
+        );
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)).0, to_yocto("4"));
+    }
+
+    #[test]
+    fn test_lostfound_claim() {
+        let (mut context, mut contract) = setup_contract();
+        // tokens being withdrawn are still in the liability.
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(2), 100)]);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.extend_whitelisted_tokens(vec![accounts(1)]);
+        // withdraw to non-registered account failed.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.exchange_callback_post_withdraw(accounts(1).into(), accounts(4).into(), U128(100));
+        assert_eq!(contract.get_user_lostfound(accounts(4))[accounts(1).as_ref()], U128(100));
+        assert_eq!(contract.get_lostfound_list(0, 10).len(), 1);
+        // non-whitelisted tokens are kept as well, and stay in the liability.
+        contract.exchange_callback_post_withdraw(accounts(2).into(), accounts(4).into(), U128(100));
+        assert_eq!(contract.get_user_lostfound(accounts(4))[accounts(2).as_ref()], U128(100));
+        assert_eq!(contract.get_token_liability(accounts(2)).0, 100);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context.attached_deposit(1).build());
+        assert_eq!(contract.claim_lostfound(accounts(1)), U128(100));
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)), U128(100));
+        assert_eq!(contract.claim_lostfound(accounts(2)), U128(100));
+        assert!(contract.get_user_lostfound(accounts(4)).is_empty());
+        assert_eq!(contract.get_number_of_lostfound_accounts(), 0);
+    }
+
+    #[test]
+    fn test_lostfound_unlisted_cap() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        let token = |i: usize| format!("token{}", i);
+        for i in 0..=account_deposit::MAX_LOSTFOUND_UNLISTED_TOKENS {
+            // increase liability as a deposit did.
+            contract.internal_increase_liability(&token(i), 100);
+            contract.exchange_callback_post_withdraw(token(i), accounts(4).into(), U128(100));
+        }
+        let lostfound = contract.get_user_lostfound(accounts(4));
+        assert_eq!(lostfound.len(), account_deposit::MAX_LOSTFOUND_UNLISTED_TOKENS);
+        // the one over the cap goes to owner, still owed.
+        let last = token(account_deposit::MAX_LOSTFOUND_UNLISTED_TOKENS);
+        assert!(!lostfound.contains_key(&last));
+        assert_eq!(
+            contract.internal_get_account(&accounts(0).into()).unwrap().get_balance(&last),
+            Some(100)
+        );
+        assert_eq!(contract.token_liabilities.get(&last), Some(100));
+    }
+
+    #[test]
+    fn test_token_liability() {
+        let (mut context, mut contract) = setup_contract();
+        create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("1"))],
+        );
+        assert_eq!(contract.get_token_liability(accounts(1)).0, to_yocto("6"));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let amount_out = swap(&mut contract, 0, accounts(1), to_yocto("1"), accounts(2));
+        // swaps don't change what is owed.
+        assert_eq!(contract.get_token_liability(accounts(1)).0, to_yocto("6"));
+        assert_eq!(contract.get_token_liability(accounts(2)).0, to_yocto("10"));
+
+        contract.withdraw(accounts(2), U128(amount_out), None, None, None);
+        assert_eq!(contract.get_token_liability(accounts(2)).0, to_yocto("10"));
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.exchange_callback_post_withdraw(accounts(2).into(), accounts(3).into(), U128(amount_out));
+        assert_eq!(
+            contract.get_token_liability(accounts(2)).0,
+            to_yocto("10") - amount_out
+        );
+
+        // contract holds 1 more token than it owes.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(to_yocto("7"))).unwrap()
+            )]
+        );
+        let report = contract.exchange_callback_solvency_report(accounts(1).into());
+        assert!(report.complete);
+        assert_eq!(report.surplus.0, to_yocto("1"));
+        assert_eq!(report.deficit.0, 0);
+
+        // first call only requests the retrieval, the same call sends it after the delay.
+        let retrieve = |contract: &mut Contract| {
+            contract.exchange_callback_retrieve_unmanaged_token(accounts(1).into(), U128(to_yocto("1")))
+        };
+        assert!(matches!(retrieve(&mut contract), PromiseOrValue::Value(())));
+        assert!(contract.pending_retrievals.get(accounts(1).as_ref()).is_some());
+        testing_env!(
+            context.block_timestamp(liability::RETRIEVAL_DELAY_SEC * 1_000_000_000).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(to_yocto("7"))).unwrap()
+            )]
+        );
+        assert!(matches!(retrieve(&mut contract), PromiseOrValue::Promise(_)));
+        assert!(contract.pending_retrievals.get(accounts(1).as_ref()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E57: retrieval requested too recently")]
+    fn test_retrieve_too_early() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5"))],
+        );
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(to_yocto("6"))).unwrap()
+            )]
+        );
+        contract.exchange_callback_retrieve_unmanaged_token(accounts(1).into(), U128(to_yocto("1")));
+        contract.exchange_callback_retrieve_unmanaged_token(accounts(1).into(), U128(to_yocto("1")));
+    }
+
+    #[test]
+    fn test_liability_underflow() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), 100)]);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        // callback doesn't fail, liability saturates at zero.
+        contract.exchange_callback_post_withdraw(accounts(1).into(), accounts(3).into(), U128(101));
+        assert_eq!(contract.get_token_liability(accounts(1)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E55: amount exceeds token surplus")]
+    fn test_retrieve_over_surplus() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5"))],
+        );
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(to_yocto("6"))).unwrap()
+            )]
+        );
+        contract.exchange_callback_retrieve_unmanaged_token(accounts(1).into(), U128(to_yocto("2")));
+    }
+
+    #[test]
+    fn test_account_portfolio() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("1"))],
+        );
+        let portfolio = contract.get_account_portfolio(accounts(3));
+        assert_eq!(portfolio.deposits[accounts(1).as_ref()], U128(to_yocto("1")));
+        assert_eq!(
+            portfolio.positions,
+            vec![portfolio::LpPosition {
+                pool_id,
+                shares: U128(to_yocto("1")),
+                amounts: vec![U128(to_yocto("5")), U128(to_yocto("10"))],
+                share_bps: 10000,
+            }]
+        );
+
+        // registered with 0 balance, no position shown.
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(to_yocto("0.00071"))
+            .build());
+        contract.mft_register(format!(":{}", pool_id), accounts(4));
+        // no exchange account to pay the index entry, synced once it has one.
+        assert!(contract.get_account_registered_pools(accounts(4)).is_empty());
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        contract.storage_deposit(None, None);
+        let prev_deposit = contract.get_user_storage_state(accounts(4)).unwrap().deposit.0;
+        testing_env!(context.attached_deposit(1).build());
+        let prev_storage = env::storage_usage();
+        contract.sync_account_pools(vec![pool_id]);
+        assert_eq!(contract.get_account_registered_pools(accounts(4)), vec![pool_id]);
+        assert_eq!(
+            prev_deposit - contract.get_user_storage_state(accounts(4)).unwrap().deposit.0,
+            (env::storage_usage() - prev_storage) as Balance * env::storage_byte_cost()
+        );
+        assert!(contract.get_account_portfolio(accounts(4)).positions.is_empty());
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.mft_transfer(format!(":{}", pool_id), accounts(4), U128(to_yocto("0.25")), None);
+        let portfolio = contract.get_account_portfolio(accounts(4));
+        assert_eq!(portfolio.positions[0].share_bps, 2500);
+        assert_eq!(
+            portfolio.positions[0].amounts,
+            vec![U128(to_yocto("1.25")), U128(to_yocto("2.5"))]
+        );
+    }
+
+    #[test]
+    fn test_mft_unregister() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        let token_id = format!(":{}", pool_id);
+        // account with storage deposit gets freed storage back into it.
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.remove_liquidity(
+            pool_id,
+            contract.get_pool_shares(pool_id, accounts(3)),
+            vec![U128(1), U128(1)],
+        );
+        assert_eq!(contract.get_account_registered_pools(accounts(3)), vec![pool_id]);
+        let prev_deposit = contract.get_user_storage_state(accounts(3)).unwrap().deposit.0;
+        let prev_storage = env::storage_usage();
+        contract.mft_unregister(token_id.clone());
+        // share entry and index entry are both refunded.
+        assert_eq!(
+            contract.get_user_storage_state(accounts(3)).unwrap().deposit.0 - prev_deposit,
+            (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost()
+        );
+        assert!(contract.get_account_registered_pools(accounts(3)).is_empty());
+
+        // registration can be paid again.
+        testing_env!(context.attached_deposit(to_yocto("0.00071")).build());
+        contract.mft_register(token_id, accounts(3));
+        assert_eq!(contract.get_account_registered_pools(accounts(3)), vec![pool_id]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E15: non-zero lp shares")]
+    fn test_mft_unregister_non_zero() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.mft_unregister(format!(":{}", pool_id));
+    }
+
+    #[test]
+    fn test_pool_oracle_twap() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("2"))],
+        );
+        let sec = 1_000_000_000;
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(10 * sec)
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.set_pool_oracle_capacity(pool_id, 2);
+        assert_eq!(contract.get_pool_oracle(pool_id).unwrap().num_observations, 1);
+
+        testing_env!(context.block_timestamp(110 * sec).attached_deposit(1).build());
+        assert_eq!(
+            contract.get_twap(pool_id, accounts(1), accounts(2), 100),
+            U128(2 * utils::PRICE_PRECISION)
+        );
+        swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+        let amounts = contract.get_pool(pool_id).amounts;
+        let spot = (utils::U256::from(amounts[1].0) * utils::U256::from(utils::PRICE_PRECISION)
+            / utils::U256::from(amounts[0].0))
+        .as_u128();
+
+        testing_env!(context.block_timestamp(210 * sec).build());
+        let twap = contract.get_twap(pool_id, accounts(1), accounts(2), 100).0;
+        assert!(twap <= spot + 1 && spot <= twap + 1);
+        let twap = contract.get_twap(pool_id, accounts(1), accounts(2), 200).0;
+        assert!(spot < twap && twap < 2 * utils::PRICE_PRECISION);
+        assert!(contract.try_get_twap(pool_id, accounts(1), accounts(2), 201).is_none());
+
+        // oldest observation is overwritten once capacity is reached.
+        swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+        let info = contract.get_pool_oracle(pool_id).unwrap();
+        assert_eq!((info.oldest_timestamp, info.latest_timestamp), (110, 210));
+        assert!(contract.try_get_twap(pool_id, accounts(1), accounts(2), 200).is_none());
+        assert!(contract.try_get_twap(pool_id, accounts(1), accounts(2), 100).is_some());
+    }
+
+    #[test]
+    fn test_pool_stats() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("3"))],
+        );
+        let hour = 3600 * 1_000_000_000;
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        // Nothing is recorded before stats are enabled.
+        swap(&mut contract, pool_id, accounts(1), to_yocto("0.1"), accounts(2));
+        assert!(!contract.is_pool_stats_enabled(pool_id));
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        contract.enable_pool_stats(pool_id);
+        assert!(contract.is_pool_stats_enabled(pool_id));
+        testing_env!(context
+            .block_timestamp(hour)
+            .attached_deposit(1)
+            .build());
+        let out1 = swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+        testing_env!(context.block_timestamp(30 * hour).build());
+        let out2 = swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+
+        // 0.25% fee, of which 16% goes to exchange and 4% referral is not used.
+        let lp_fee = to_yocto("1") * 25 / 10_000 * 8_400 / 10_000;
+        let stats = contract.get_pool_stats(pool_id, 24);
+        assert_eq!(stats.volume_in, vec![U128(to_yocto("1")), U128(0)]);
+        assert_eq!(stats.volume_out, vec![U128(0), U128(out2)]);
+        assert_eq!(stats.lp_fees, vec![U128(lp_fee), U128(0)]);
+        let stats = contract.get_pool_stats(pool_id, 168);
+        assert_eq!(stats.volume_in, vec![U128(to_yocto("2")), U128(0)]);
+        assert_eq!(stats.volume_out, vec![U128(0), U128(out1 + out2)]);
+
+        // bucket of hour 1 is reused a week later.
+        testing_env!(context.block_timestamp(169 * hour).build());
+        assert_eq!(contract.get_pool_stats(pool_id, 168).volume_out, vec![U128(0), U128(out2)]);
+        swap(&mut contract, pool_id, accounts(2), out2, accounts(1));
+        let stats = contract.get_pool_stats(pool_id, 1);
+        assert_eq!(stats.volume_in, vec![U128(0), U128(out2)]);
+    }
+
+    #[test]
+    fn test_return_detail() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        let quote = |contract: &Contract, actions: Vec<SwapAction>| {
+            contract.get_return_detail(actions, Some(accounts(3)))
+        };
+        let action = |token_in: ValidAccountId, amount_in: Option<Balance>, token_out: ValidAccountId| {
+            SwapAction {
+                pool_id,
+                token_in: token_in.into(),
+                amount_in: amount_in.map(U128),
+                token_out: token_out.into(),
+                min_amount_out: U128(0),
+            }
+        };
+        let detail = quote(&contract, vec![action(accounts(1), Some(to_yocto("1")), accounts(2))]);
+        let step = &detail.steps[0];
+        assert_eq!(
+            detail.amount_out,
+            contract.get_return(pool_id, accounts(1), U128(to_yocto("1")), accounts(2))
+        );
+        assert_eq!(step.fee_token, accounts(1).to_string());
+        // 0.25% fee, 16% of it to exchange and 4% to the registered referral.
+        assert_eq!(step.total_fee, U128(to_yocto("1") * 25 / 10_000));
+        assert_eq!(step.admin_fee.0, step.total_fee.0 * 1_600 / 10_000);
+        assert_eq!(step.referral_fee.0, step.total_fee.0 * 400 / 10_000);
+        assert_eq!(step.lp_fee.0, step.total_fee.0 - step.admin_fee.0 - step.referral_fee.0);
+        assert_eq!(step.spot_price_before, Some(U128(2 * utils::PRICE_PRECISION)));
+        assert!(step.spot_price_after.unwrap().0 < 2 * utils::PRICE_PRECISION);
+        // input x = 0.9975 after fee gets x / (5 + x) less than at spot price.
+        assert_eq!(step.price_impact_bps, 1663);
+        assert_eq!(step.amp, None);
+
+        // second step sees the pool after the first one, and the quote matches the actual swap.
+        let detail = quote(
+            &contract,
+            vec![
+                action(accounts(1), Some(to_yocto("1")), accounts(2)),
+                action(accounts(2), None, accounts(1)),
+            ],
+        );
+        assert_eq!(detail.steps[1].amount_in, detail.steps[0].amount_out);
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("1"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        let out = contract.swap(
+            vec![
+                action(accounts(1), Some(to_yocto("1")), accounts(2)),
+                action(accounts(2), None, accounts(1)),
+            ],
+            Some(accounts(3)),
+        );
+        assert_eq!(detail.amount_out, out);
+    }
+
+    #[test]
+    fn test_simulate_actions() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("2"))],
+        );
+        let action = |amount_in: Option<Balance>, min_amount_out: Balance| {
+            Action::Swap(SwapAction {
+                pool_id,
+                token_in: accounts(1).into(),
+                amount_in: amount_in.map(U128),
+                token_out: accounts(2).into(),
+                min_amount_out: U128(min_amount_out),
+            })
+        };
+        let expected = contract.get_return(pool_id, accounts(1), U128(to_yocto("1")), accounts(2));
+        let result = contract.simulate_actions(accounts(3), vec![action(Some(to_yocto("1")), 1)], None);
+        assert_eq!(result.error, None);
+        assert_eq!(result.steps[0].amount_out, expected);
+        assert_eq!(result.balances[accounts(1).as_ref()], U128(to_yocto("1")));
+        assert_eq!(result.balances[accounts(2).as_ref()], expected);
+
+        let result = contract.simulate_actions(
+            accounts(3),
+            vec![action(Some(to_yocto("1")), 1), action(Some(to_yocto("1")), expected.0)],
+            None,
+        );
+        assert_eq!(result.failed_step, Some(1));
+        assert_eq!(result.error, Some("ERR_MIN_AMOUNT".to_string()));
+        let result = contract.simulate_actions(accounts(3), vec![action(Some(to_yocto("3")), 1)], None);
+        assert_eq!(result.error, Some(ERR22_NOT_ENOUGH_TOKENS.to_string()));
+        let result = contract.simulate_actions(accounts(4), vec![action(Some(to_yocto("1")), 1)], None);
+        assert_eq!(result.error, Some(ERR10_ACC_NOT_REGISTERED.to_string()));
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.remove_whitelisted_tokens(vec![accounts(2)]);
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![(accounts(1), to_yocto("1"))]);
+        let result = contract.simulate_actions(accounts(4), vec![action(Some(to_yocto("1")), 1)], None);
+        assert_eq!(result.failed_step, Some(0));
+        assert_eq!(result.error, Some(ERR27_DEPOSIT_NEEDED.to_string()));
+
+        // nothing was changed by the simulations.
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)), U128(to_yocto("2")));
+        assert_eq!(contract.get_pool(pool_id).amounts, vec![U128(to_yocto("5")), U128(to_yocto("10"))]);
+    }
+
+    #[test]
+    fn test_simulate_multi_hop_unregistered_token() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_a = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        let pool_b = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(2), to_yocto("10")), (accounts(5), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![(accounts(1), to_yocto("1"))]);
+        // Token 2 is whitelisted but not registered in the account, the first hop registers it.
+        let actions = || vec![
+            SwapAction {
+                pool_id: pool_a,
+                token_in: accounts(1).into(),
+                amount_in: Some(U128(to_yocto("1"))),
+                token_out: accounts(2).into(),
+                min_amount_out: U128(1),
+            },
+            SwapAction {
+                pool_id: pool_b,
+                token_in: accounts(2).into(),
+                amount_in: None,
+                token_out: accounts(5).into(),
+                min_amount_out: U128(1),
+            },
+        ];
+        let result = contract.simulate_actions(
+            accounts(4),
+            actions().into_iter().map(Action::Swap).collect(),
+            None,
+        );
+        assert_eq!(result.error, None);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(0)
+            .build());
+        let amount_out = contract.swap(actions(), None);
+        assert_eq!(result.steps[1].amount_out, amount_out);
+        assert_eq!(contract.get_deposit(accounts(4), accounts(5)), amount_out);
+    }
+
+    #[test]
+    fn test_try_get_return() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        assert_eq!(
+            contract.try_get_return(pool_id, accounts(1), U128(to_yocto("1")), accounts(2)),
+            Ok(contract.get_return(pool_id, accounts(1), U128(to_yocto("1")), accounts(2)))
+        );
+        assert_eq!(
+            contract.try_get_return(pool_id, accounts(1), U128(to_yocto("1")), accounts(4)),
+            Err(ErrorCode::E63MissingToken)
+        );
+        assert_eq!(
+            contract.try_get_return(pool_id, accounts(1), U128(0), accounts(2)),
+            Err(ErrorCode::E31ZeroAmount)
+        );
+        assert_eq!(
+            contract.try_get_return(pool_id + 1, accounts(1), U128(to_yocto("1")), accounts(2)),
+            Err(ErrorCode::E86PoolNotFound)
+        );
+        assert_eq!(
+            contract.try_predict_remove_liquidity(pool_id, U128(to_yocto("1000"))),
+            Err(ErrorCode::E34InsufficientLpShares)
+        );
+        assert_eq!(
+            contract.try_get_pool_share_price(pool_id, Some(accounts(4))),
+            Err(ErrorCode::E63MissingToken)
+        );
+        assert_eq!(ErrorCode::E63MissingToken.message(), ERR63_MISSING_TOKEN);
+    }
+
+    #[test]
+    fn test_add_liquidity_single() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![(accounts(1), to_yocto("2"))]);
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        let shares = contract.add_liquidity_single(pool_id, accounts(1), U128(to_yocto("1")), U128(1));
+        assert_eq!(contract.get_pool_shares(pool_id, accounts(4)), shares);
+        let dust_in = contract.get_deposit(accounts(4), accounts(1)).0 - to_yocto("1");
+        let dust_out = contract.get_deposit(accounts(4), accounts(2)).0;
+        // Only rounding is left out.
+        assert!(dust_in < 100 && dust_out < 100);
+        let pool = contract.get_pool(pool_id);
+        assert_eq!(pool.amounts[0].0 + dust_in, to_yocto("6"));
+    }
+
+    #[test]
+    fn test_ft_on_transfer_add_liquidity() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![(accounts(1), 0), (accounts(2), 0)]);
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(to_yocto("0.00071"))
+            .build());
+        contract.mft_register(":0".to_string(), accounts(4));
+        // no deposit comes with the transfer, storage is paid from sender's storage deposit.
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(0)
+            .build());
+        contract.ft_on_transfer(
+            accounts(4),
+            U128(to_yocto("1")),
+            format!("{{\"pool_id\": {}, \"min_shares\": \"1\"}}", pool_id),
+        );
+        assert!(contract.get_pool_shares(pool_id, accounts(4)).0 > 0);
+        // Only rounding is left in the inner account.
+        assert!(contract.get_deposit(accounts(4), accounts(1)).0 < 100);
+
+        contract.ft_on_transfer(
+            accounts(4),
+            U128(to_yocto("1")),
+            format!("{{\"account_id\": \"{}\"}}", accounts(3)),
+        );
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)), U128(to_yocto("1")));
+    }
+
+    #[test]
+    fn test_bonded_stable_pool() {
+        use crate::bonded_pool::PoolBondState;
+
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("21"))
+            .build());
+        let pool_id = contract.add_bonded_stable_swap_pool(
+            vec![accounts(1), accounts(2)],
+            vec![18, 18],
+            5,
+            100,
+            None,
+        );
+        assert_eq!(contract.get_pool_bond(pool_id).unwrap().state, PoolBondState::Verifying);
+
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![ft_metadata_result(18), ft_metadata_result(18)]
+        );
+        assert!(contract.exchange_callback_check_pool_decimals(pool_id, vec![18, 18]));
+        let bond = contract.get_pool_bond(pool_id).unwrap();
+        assert_eq!(bond.state, PoolBondState::Pending);
+        assert_eq!(bond.amount, U128(to_yocto("20")));
+
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("100")), (accounts(2), to_yocto("100"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        contract.add_stable_liquidity(pool_id, vec![U128(to_yocto("50")), U128(to_yocto("50"))], U128(1));
+        testing_env!(context.attached_deposit(1).build());
+        let result = contract.simulate_actions(
+            accounts(3),
+            vec![Action::Swap(SwapAction {
+                pool_id,
+                token_in: accounts(1).into(),
+                amount_in: Some(U128(to_yocto("1"))),
+                token_out: accounts(2).into(),
+                min_amount_out: U128(1),
+            })],
+            None,
+        );
+        assert_eq!(result.error, Some(ERR74_POOL_NOT_ACTIVE.to_string()));
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.approve_bonded_pool(pool_id);
+        assert!(contract.get_pool_bond(pool_id).is_none());
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        assert!(swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2)) > 0);
+
+        // Wrong decimals reject the pool and return the bond.
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("21"))
+            .build());
+        let pool_id = contract.add_bonded_stable_swap_pool(
+            vec![accounts(1), accounts(4)],
+            vec![18, 6],
+            5,
+            100,
+            None,
+        );
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![ft_metadata_result(18), ft_metadata_result(18)]
+        );
+        assert!(!contract.exchange_callback_check_pool_decimals(pool_id, vec![18, 6]));
+        let bond = contract.get_pool_bond(pool_id).unwrap();
+        assert_eq!(bond.state, PoolBondState::Rejected);
+        assert_eq!(bond.amount, U128(0));
+    }
+
+    #[test]
+    fn test_token_metadata() {
+        let (mut context, mut contract) = setup_contract();
+        contract.extend_whitelisted_tokens(vec![accounts(1)]);
+        assert!(contract.get_token_metadata(accounts(1)).is_none());
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![ft_metadata_result(6)]
+        );
+        assert!(contract.exchange_callback_token_metadata(accounts(1).into()));
+        let metadata = contract.get_token_metadata(accounts(1)).unwrap();
+        assert_eq!(metadata.symbol, "TKN");
+        assert_eq!(metadata.decimals, 6);
+
+        // Failed call keeps the metadata already known.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        assert!(!contract.exchange_callback_token_metadata(accounts(1).into()));
+        assert_eq!(contract.get_token_metadata(accounts(1)), Some(metadata));
+
+        // Anyone can refresh a whitelisted token.
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.refresh_token_metadata(vec![accounts(1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E77: decimals differ from token metadata")]
+    fn test_deny_stable_pool_wrong_decimals() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![ft_metadata_result(6)]
+        );
+        contract.exchange_callback_token_metadata(accounts(1).into());
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        contract.add_stable_swap_pool(vec![accounts(1), accounts(2)], vec![18, 18], 5, 100);
+    }
+
+    #[test]
+    fn test_deactivate_stable_pool_wrong_decimals() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![ft_metadata_result(18)]
+        );
+        contract.exchange_callback_token_metadata(accounts(1).into());
+        testing_env!(context.attached_deposit(to_yocto("1")).build());
+        let pool_id = contract.add_stable_swap_pool(vec![accounts(1), accounts(2)], vec![18, 18], 5, 100);
+
+        // Only the token missing from the registry is fetched, it has other decimals.
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![ft_metadata_result(6)]
+        );
+        assert!(!contract.exchange_callback_check_token_decimals(pool_id, vec![accounts(2).into()]));
+        assert_eq!(contract.get_token_metadata(accounts(2)).unwrap().decimals, 6);
+        assert_eq!(contract.get_deactivated_pools(), vec![pool_id]);
+        assert!(!contract.is_pool_active(pool_id));
+    }
+
+    #[test]
+    fn test_denied_tokens() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("1")), (accounts(2), to_yocto("1"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.extend_denied_tokens(vec![accounts(2)], "bridge exploit".to_string());
+        assert_eq!(contract.get_denied_tokens()[accounts(2).as_ref()], "bridge exploit");
+
+        // Swapping out of the token and removing liquidity still work.
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        assert!(swap(&mut contract, pool_id, accounts(2), to_yocto("1"), accounts(1)) > 0);
+        let result = contract.simulate_actions(
+            accounts(3),
+            vec![Action::Swap(SwapAction {
+                pool_id,
+                token_in: accounts(1).into(),
+                amount_in: Some(U128(to_yocto("1"))),
+                token_out: accounts(2).into(),
+                min_amount_out: U128(1),
+            })],
+            None,
+        );
+        assert_eq!(result.error, Some(ERR16_TOKEN_DENIED.to_string()));
+        let shares = contract.get_pool_shares(pool_id, accounts(3));
+        contract.remove_liquidity(pool_id, shares, vec![U128(1), U128(1)]);
+        assert!(contract.get_deposit(accounts(3), accounts(2)).0 > 0);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.remove_denied_tokens(vec![accounts(2)]);
+        assert!(contract.get_denied_tokens().is_empty());
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        contract.ft_on_transfer(accounts(3), U128(1), "".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "E16: token denied")]
+    fn test_deny_deposit_denied_token() {
+        let (mut context, mut contract) = setup_contract();
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), 1)]);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.extend_denied_tokens(vec![accounts(1)], "deprecated".to_string());
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        contract.ft_on_transfer(accounts(3), U128(1), "".to_string());
+    }
+
+    #[test]
+    fn test_token_migration() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(3), vec![(accounts(1), to_yocto("1"))]);
+        let liability = contract.get_token_liability(accounts(1)).0;
+        assert_eq!(liability, to_yocto("6"));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.extend_whitelisted_tokens(vec![accounts(1)]);
+        contract.propose_token_migration(accounts(1), accounts(4));
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .block_timestamp(token_migration::TOKEN_MIGRATION_TIMELOCK_SEC * 1_000_000_000)
+                .build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(liability)).unwrap()
+            )]
+        );
+        // Metadata of the tokens is not read yet.
+        assert!(!contract.exchange_callback_verify_token_migration(accounts(1).into()));
+        record_token_metadata(&mut context, &mut contract, vec![(accounts(1), 18), (accounts(4), 18)]);
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(liability - 1)).unwrap()
+            )]
+        );
+        assert!(!contract.exchange_callback_verify_token_migration(accounts(1).into()));
+        assert!(!contract.get_denied_tokens().contains_key(accounts(1).as_ref()));
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(liability)).unwrap()
+            )]
+        );
+        assert!(contract.exchange_callback_verify_token_migration(accounts(1).into()));
+        // Deposits of the old token stop as soon as the balance is verified.
+        assert!(contract.get_denied_tokens().contains_key(accounts(1).as_ref()));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.migrate_token_pools(accounts(1), 10), 1);
+        assert_eq!(
+            contract.get_pool(pool_id).token_account_ids,
+            vec![accounts(4).to_string(), accounts(2).to_string()]
+        );
+        contract.migrate_token_accounts(accounts(1), vec![accounts(3), accounts(5)]);
+        assert_eq!(contract.get_deposit(accounts(3), accounts(4)), U128(to_yocto("1")));
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)), U128(0));
+        assert_eq!(contract.get_token_liability(accounts(1)), U128(0));
+        assert_eq!(contract.get_token_liability(accounts(4)), U128(liability));
+        assert_eq!(contract.get_token_migration(accounts(1)).unwrap().num_accounts, 1);
+        assert!(contract.get_denied_tokens().contains_key(accounts(1).as_ref()));
+        let whitelist = contract.get_whitelisted_tokens();
+        assert!(whitelist.contains(&accounts(4).to_string()) && !whitelist.contains(&accounts(1).to_string()));
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        assert!(swap(&mut contract, pool_id, accounts(4), to_yocto("1"), accounts(2)) > 0);
+    }
+
+    #[test]
+    fn test_token_migration_stable_pool_decimals_changed() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.add_stable_swap_pool(vec![accounts(1), accounts(2)], vec![18, 18], 5, 100);
+        testing_env!(context.attached_deposit(1).build());
+        contract.propose_token_migration(accounts(1), accounts(4));
+        record_token_metadata(&mut context, &mut contract, vec![(accounts(1), 18), (accounts(4), 18)]);
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .block_timestamp(token_migration::TOKEN_MIGRATION_TIMELOCK_SEC * 1_000_000_000)
+                .build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&U128(0)).unwrap())]
+        );
+        assert!(contract.exchange_callback_verify_token_migration(accounts(1).into()));
+        // Registry refreshed with other decimals since, the pool is skipped and the batch goes on.
+        record_token_metadata(&mut context, &mut contract, vec![(accounts(4), 6)]);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.migrate_token_pools(accounts(1), 10), 1);
+        assert_eq!(
+            contract.get_pool(0).token_account_ids,
+            vec![accounts(1).to_string(), accounts(2).to_string()]
+        );
+    }
+
+    #[test]
+    fn test_migrate_liquidity() {
+        let (mut context, mut contract) = setup_contract();
+        let from_pool = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let simple_pool = contract.add_simple_pool(vec![accounts(2), accounts(1)], 5);
+        let stable_pool = contract.add_stable_swap_pool(vec![accounts(1), accounts(2)], vec![18, 18], 5, 100);
+        let shares = contract.get_pool_shares(from_pool, accounts(3)).0;
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(to_yocto("0.01"))
+            .build());
+        let out_shares = contract.migrate_liquidity(from_pool, simple_pool, U128(shares / 2), U128(1));
+        assert_eq!(contract.get_pool_shares(simple_pool, accounts(3)), out_shares);
+        assert_eq!(contract.get_pool_shares(from_pool, accounts(3)).0, shares - shares / 2);
+        // Empty pool takes all, in its own token order.
+        let pool = contract.get_pool(simple_pool);
+        assert_eq!(pool.amounts, vec![U128(to_yocto("5")), U128(to_yocto("2.5"))]);
+        assert_eq!(contract.get_deposit(accounts(3), accounts(1)), U128(0));
+
+        let out_shares = contract.migrate_liquidity(from_pool, stable_pool, U128(shares - shares / 2), U128(1));
+        assert_eq!(contract.get_pool_shares(stable_pool, accounts(3)), out_shares);
+        assert_eq!(contract.get_pool_shares(from_pool, accounts(3)), U128(0));
+    }
+
+    /// Creates a pool of accounts(3) taking half of the exchange fee, and swaps on it.
+    fn setup_pool_creator_fee(context: &mut VMContextBuilder, contract: &mut Contract) -> u64 {
+        let pool_id = create_pool_with_liquidity(
+            context,
+            contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_creator_fee_cap(5000);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.set_pool_creator_fee(pool_id, 5000);
+        deposit_tokens(context, contract, accounts(1), vec![(accounts(1), to_yocto("1"))]);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        swap(contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+        pool_id
+    }
+
+    #[test]
+    fn test_pool_creator_fee() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = setup_pool_creator_fee(&mut context, &mut contract);
+        let creator = contract.get_pool_creator(pool_id).unwrap();
+        assert_eq!(creator.creator_id, accounts(3).to_string());
+        let exchange_shares = contract.get_pool_shares(pool_id, ValidAccountId::try_from(env::current_account_id()).unwrap()).0;
+        assert!(exchange_shares > 0);
+        assert_eq!(creator.unclaimed_shares.0, exchange_shares / 2);
+
+        // Owner only can take the exchange's part.
+        deposit_tokens(&mut context, &mut contract, accounts(0), vec![]);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.remove_exchange_fee_liquidity(
+            pool_id,
+            U128(exchange_shares - creator.unclaimed_shares.0),
+            vec![U128(1), U128(1)],
+        );
+
+        let prev_shares = contract.get_pool_shares(pool_id, accounts(3)).0;
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.claim_pool_creator_fee(pool_id), creator.unclaimed_shares);
+        assert_eq!(
+            contract.get_pool_shares(pool_id, accounts(3)).0,
+            prev_shares + creator.unclaimed_shares.0
+        );
+        assert_eq!(contract.get_pool_shares(pool_id, ValidAccountId::try_from(env::current_account_id()).unwrap()).0, 0);
+        assert_eq!(contract.get_pool_creator(pool_id).unwrap().unclaimed_shares, U128(0));
+    }
+
+    #[test]
+    fn test_pool_creator_fee_register() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = setup_pool_creator_fee(&mut context, &mut contract);
+        let unclaimed_shares = contract.get_pool_creator(pool_id).unwrap().unclaimed_shares;
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.remove_liquidity(
+            pool_id,
+            contract.get_pool_shares(pool_id, accounts(3)),
+            vec![U128(1), U128(1)],
+        );
+        contract.mft_unregister(format!(":{}", pool_id));
+
+        // Creator gets registered in the pool again, paid by attached NEAR.
+        testing_env!(context.attached_deposit(to_yocto("0.00071")).build());
+        assert_eq!(contract.claim_pool_creator_fee(pool_id), unclaimed_shares);
+        assert_eq!(contract.get_pool_shares(pool_id, accounts(3)), unclaimed_shares);
+
+        // Owner's stable pool has no creator.
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(to_yocto("1"))
+            .build());
+        let stable_pool = contract.add_stable_swap_pool(vec![accounts(1), accounts(2)], vec![18, 18], 5, 100);
+        assert!(contract.get_pool_creator(stable_pool).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E37: exchange shares reserved for pool creator")]
+    fn test_remove_creator_reserved_shares() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = setup_pool_creator_fee(&mut context, &mut contract);
+        let exchange_shares = contract.get_pool_shares(pool_id, ValidAccountId::try_from(env::current_account_id()).unwrap());
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.remove_exchange_fee_liquidity(pool_id, exchange_shares, vec![U128(1), U128(1)]);
+    }
+
+    #[test]
+    fn test_harvest_exchange_fees() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(1), vec![(accounts(1), to_yocto("2"))]);
+        deposit_tokens(&mut context, &mut contract, accounts(4), vec![]);
+        let sec = 1_000_000_000;
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(10 * sec)
+            .attached_deposit(to_yocto("1"))
+            .build());
+        contract.set_pool_oracle_capacity(pool_id, 10);
+        let exchange_id = ValidAccountId::try_from(env::current_account_id()).unwrap();
+        let mut swap_pools = HashMap::new();
+        swap_pools.insert(accounts(1).to_string(), pool_id);
+        let mut config = FeeHarvestConfig {
+            treasury_id: accounts(4).to_string(),
+            treasury_token_id: Some(accounts(2).to_string()),
+            swap_pools,
+            max_slippage_bps: 0,
+            twap_window_sec: 100,
+        };
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.set_fee_harvest_config(config.clone());
+
+        // No slippage allowed, so tokens are credited as they are.
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(110 * sec)
+            .build());
+        swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+        testing_env!(context.block_timestamp(210 * sec).build());
+        assert_eq!(contract.harvest_exchange_fees(0, 10), 1);
+        assert_eq!(contract.get_pool_shares(pool_id, exchange_id.clone()), U128(0));
+        let token1 = contract.get_deposit(accounts(4), accounts(1)).0;
+        let token2 = contract.get_deposit(accounts(4), accounts(2)).0;
+        assert!(token1 > 0 && token2 > 0);
+
+        config.max_slippage_bps = 100;
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.set_fee_harvest_config(config);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(310 * sec)
+            .build());
+        swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+        testing_env!(context.block_timestamp(410 * sec).build());
+        assert_eq!(contract.harvest_exchange_fees(0, 10), 1);
+        // Token 1 is swapped into the treasury token.
+        assert_eq!(contract.get_deposit(accounts(4), accounts(1)).0, token1);
+        assert!(contract.get_deposit(accounts(4), accounts(2)).0 > token2);
+    }
+
+    #[test]
+    fn test_harvest_to_unregistered_treasury() {
+        let (mut context, mut contract) = setup_contract();
+        let pool_id = create_pool_with_liquidity(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            vec![(accounts(1), to_yocto("5")), (accounts(2), to_yocto("10"))],
+        );
+        deposit_tokens(&mut context, &mut contract, accounts(1), vec![(accounts(1), to_yocto("2"))]);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.set_fee_harvest_config(FeeHarvestConfig {
+            treasury_id: accounts(5).to_string(),
+            treasury_token_id: None,
+            swap_pools: HashMap::new(),
+            max_slippage_bps: 0,
+            twap_window_sec: 0,
+        });
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        swap(&mut contract, pool_id, accounts(1), to_yocto("1"), accounts(2));
+        let liabilities = (
+            contract.get_token_liability(accounts(1)),
+            contract.get_token_liability(accounts(2)),
+        );
+        assert_eq!(contract.harvest_exchange_fees(0, 10), 1);
+        // Non-whitelisted tokens are kept in treasury's lost-found, still owed.
+        let lostfound = contract.get_user_lostfound(accounts(5));
+        assert!(lostfound[accounts(1).as_ref()].0 > 0 && lostfound[accounts(2).as_ref()].0 > 0);
+        assert_eq!(
+            (contract.get_token_liability(accounts(1)), contract.get_token_liability(accounts(2))),
+            liabilities
+        );
+    }
 }
@@ -2,7 +2,7 @@ use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{ext_contract, near_bindgen, Balance, PromiseOrValue};
 
-use crate::utils::{GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER, NO_DEPOSIT};
+use crate::utils::{GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER, NO_DEPOSIT};
 use crate::*;
 
 #[ext_contract(ext_self)]
@@ -73,13 +73,14 @@ impl Contract {
                 let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
                 pool.share_transfer(sender_id, receiver_id, amount);
                 self.pools.replace(pool_id, &pool);
-                log!(
-                    "Transfer shares {} pool: {} from {} to {}",
+                Event::LpTransfer {
                     pool_id,
-                    amount,
                     sender_id,
-                    receiver_id
-                );
+                    receiver_id,
+                    amount: U128(amount),
+                    memo: memo.as_deref(),
+                }
+                .emit();
             }
             TokenOrPool::Token(token_id) => {
                 let mut sender_account: Account = self.internal_unwrap_account(&sender_id);
@@ -89,18 +90,16 @@ impl Contract {
                 receiver_account.deposit(&token_id, amount);
                 self.internal_save_account(&sender_id, sender_account);
                 self.internal_save_account(&receiver_id, receiver_account);
-                log!(
-                    "Transfer {}: {} from {} to {}",
-                    token_id,
-                    amount,
+                Event::Transfer {
+                    token_id: &token_id,
                     sender_id,
-                    receiver_id
-                );
+                    receiver_id,
+                    amount: U128(amount),
+                    memo: memo.as_deref(),
+                }
+                .emit();
             }
         }
-        if let Some(memo) = memo {
-            log!("Memo: {}", memo);
-        }
     }
 
     fn internal_mft_balance(&self, token_id: String, account_id: &AccountId) -> Balance {
@@ -144,6 +143,35 @@ impl Contract {
                 pool.share_register(account_id.as_ref());
                 self.pools.replace(pool_id, &pool);
                 self.internal_check_storage(prev_storage);
+                self.internal_index_account_pool(account_id.as_ref(), pool_id);
+            }
+        }
+    }
+
+    /// Unregister LP token of given pool for the caller, it must have no shares left.
+    /// Freed storage goes to caller's storage deposit if it has an account, otherwise is sent back to it.
+    /// Fails if token_id is not a pool.
+    #[payable]
+    pub fn mft_unregister(&mut self, token_id: String) {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let account_id = env::predecessor_account_id();
+        match parse_token_id(token_id) {
+            TokenOrPool::Token(_) => env::panic(b"ERR_INVALID_UNREGISTER"),
+            TokenOrPool::Pool(pool_id) => {
+                let prev_storage = env::storage_usage();
+                let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+                pool.share_unregister(&account_id);
+                self.pools.replace(pool_id, &pool);
+                let refund = (prev_storage - env::storage_usage()) as Balance
+                    * env::storage_byte_cost();
+                if let Some(mut account) = self.internal_get_account(&account_id) {
+                    account.near_amount += refund;
+                    self.internal_save_account(&account_id, account);
+                } else if refund > 0 {
+                    Promise::new(account_id.clone()).transfer(refund);
+                }
+                self.internal_unindex_account_pool(&account_id, pool_id);
             }
         }
     }
@@ -180,6 +208,11 @@ impl Contract {
     ) -> PromiseOrValue<U128> {
         assert_one_yocto();
         self.assert_contract_running();
+        assert!(
+            env::prepaid_gas() >= GAS_FOR_FT_TRANSFER_CALL + GAS_FOR_BASIC_OP,
+            "{}",
+            ERR44_TRANSFER_CALL_GAS
+        );
         let sender_id = env::predecessor_account_id();
         self.internal_mft_transfer(
             token_id.clone(),
@@ -249,6 +282,112 @@ impl Contract {
         U128(unused_amount)
     }
 
+    /// Allows `spender_id` to move up to `amount` of given internal token on behalf of the caller.
+    /// Overwrites any previous allowance for the same token and spender.
+    /// Attached NEAR should be enough to cover the storage of a new allowance, the rest is refunded.
+    #[payable]
+    pub fn mft_approve(&mut self, token_id: String, spender_id: ValidAccountId, amount: U128) {
+        assert!(
+            env::attached_deposit() > 0,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        self.assert_contract_running();
+        let owner_id = env::predecessor_account_id();
+        assert_ne!(&owner_id, spender_id.as_ref(), "{}", ERR33_TRANSFER_TO_SELF);
+        if let TokenOrPool::Pool(pool_id) = parse_token_id(token_id.clone()) {
+            assert!(pool_id < self.pools.len(), "ERR_NO_POOL");
+        }
+        let prev_storage = env::storage_usage();
+        self.mft_allowances.insert(
+            &(token_id.clone(), owner_id.clone(), spender_id.as_ref().clone()),
+            &amount.0,
+        );
+        self.internal_check_storage(prev_storage);
+        Event::MftApproval {
+            token_id: &token_id,
+            owner_id: &owner_id,
+            spender_id: spender_id.as_ref(),
+            amount,
+        }
+        .emit();
+    }
+
+    /// Removes allowance given to `spender_id` for given internal token.
+    /// Storage released by the allowance is refunded to the caller.
+    #[payable]
+    pub fn mft_revoke(&mut self, token_id: String, spender_id: ValidAccountId) {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        self.mft_allowances
+            .remove(&(token_id.clone(), owner_id.clone(), spender_id.as_ref().clone()))
+            .expect(ERR36_NO_ALLOWANCE);
+        let refund = (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+        if refund > 0 {
+            Promise::new(owner_id.clone()).transfer(refund);
+        }
+        Event::MftApproval {
+            token_id: &token_id,
+            owner_id: &owner_id,
+            spender_id: spender_id.as_ref(),
+            amount: U128(0),
+        }
+        .emit();
+    }
+
+    /// Returns how much of given internal token `spender_id` can move on behalf of `owner_id`.
+    pub fn mft_allowance(
+        &self,
+        token_id: String,
+        owner_id: ValidAccountId,
+        spender_id: ValidAccountId,
+    ) -> U128 {
+        U128(
+            self.mft_allowances
+                .get(&(token_id, owner_id.into(), spender_id.into()))
+                .unwrap_or(0),
+        )
+    }
+
+    /// Transfer internal tokens of `owner_id` to `receiver_id`, spending caller's allowance.
+    /// Allowance spent in full is removed, and its storage refunded to the owner.
+    #[payable]
+    pub fn mft_transfer_from(
+        &mut self,
+        token_id: String,
+        owner_id: ValidAccountId,
+        receiver_id: ValidAccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        self.assert_contract_running();
+        let key = (
+            token_id.clone(),
+            owner_id.as_ref().clone(),
+            env::predecessor_account_id(),
+        );
+        let allowance = self.mft_allowances.get(&key).unwrap_or(0);
+        assert!(allowance >= amount.0, "{}", ERR35_INSUFFICIENT_ALLOWANCE);
+        if allowance == amount.0 {
+            let prev_storage = env::storage_usage();
+            self.mft_allowances.remove(&key);
+            let refund = (prev_storage - env::storage_usage()) as Balance * env::storage_byte_cost();
+            if refund > 0 {
+                Promise::new(owner_id.as_ref().clone()).transfer(refund);
+            }
+        } else {
+            self.mft_allowances.insert(&key, &(allowance - amount.0));
+        }
+        self.internal_mft_transfer(
+            token_id,
+            owner_id.as_ref(),
+            receiver_id.as_ref(),
+            amount.0,
+            memo,
+        );
+    }
+
     pub fn mft_metadata(&self, token_id: String) -> FungibleTokenMetadata {
         match parse_token_id(token_id) {
             TokenOrPool::Pool(pool_id) => {
@@ -0,0 +1,354 @@
+//! Time-weighted average prices of pools.
+//!
+//! A pool with an oracle accumulates the spot price of each of its tokens,
+//! quoted in the pool's first token, multiplied by the seconds the price held.
+//! Snapshots of the accumulators are kept in a ring buffer of bounded capacity,
+//! and the average price over a window is the difference of the accumulators at its ends.
+//!
+//! The spot price is taken after each swap or liquidity change and only starts counting
+//! from the next second, so moving a pool and moving it back within a block has no effect.
+//! Accumulators wrap around on overflow, only their differences are meaningful.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, Balance, Promise, StorageUsage};
+
+use crate::pool::SPOT_PRICES_PRECISION;
+use crate::utils::{FEE_DIVISOR, PRICE_PRECISION, U256};
+use crate::*;
+
+/// Max number of observations kept for one pool.
+pub const MAX_ORACLE_CAPACITY: u32 = 10_000;
+/// Storage of an observation apart from its accumulators, including the record overhead.
+const OBSERVATION_BASE_STORAGE: StorageUsage = 72;
+/// Storage of one accumulator.
+const ACCUMULATOR_STORAGE: StorageUsage = 32;
+
+/// Accumulated price of each pool token at a given time.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct Observation {
+    /// Block timestamp in seconds.
+    pub timestamp: u64,
+    /// Sum of spot price times seconds, in pool tokens sequence.
+    pub cumulatives: Vec<[u64; 4]>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PoolOracle {
+    /// Max number of observations kept, paid for when the oracle is enabled or grown.
+    pub capacity: u32,
+    /// Position of the latest observation in `observations`.
+    pub last_index: u64,
+    /// Spot prices after the latest change, in pool tokens sequence.
+    /// Empty until the pool has liquidity.
+    pub spot_prices: Vec<[u64; 4]>,
+    /// Ring buffer of observations, the one after `last_index` is the oldest.
+    pub observations: Vector<Observation>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PoolOracleInfo {
+    pub capacity: u32,
+    pub num_observations: u64,
+    /// Timestamps in seconds of the oldest and latest observations.
+    pub oldest_timestamp: u64,
+    pub latest_timestamp: u64,
+}
+
+fn now_sec() -> u64 {
+    env::block_timestamp() / 1_000_000_000
+}
+
+/// Storage of a single observation in a pool with given number of tokens.
+fn observation_storage(num_tokens: usize) -> StorageUsage {
+    OBSERVATION_BASE_STORAGE + ACCUMULATOR_STORAGE * num_tokens as StorageUsage
+}
+
+impl PoolOracle {
+    fn new(pool_id: u64, pool: &Pool) -> Self {
+        let mut observations = Vector::new(StorageKey::PoolObservations { pool_id });
+        observations.push(&Observation {
+            timestamp: now_sec(),
+            cumulatives: vec![[0; 4]; pool.tokens().len()],
+        });
+        let mut oracle = Self {
+            capacity: 1,
+            last_index: 0,
+            spot_prices: vec![],
+            observations,
+        };
+        oracle.set_spot_prices(pool);
+        oracle
+    }
+
+    /// Keeps the previous prices if the pool has no liquidity now.
+    fn set_spot_prices(&mut self, pool: &Pool) {
+        if let Some(prices) = pool.spot_prices(U256::from(SPOT_PRICES_PRECISION)) {
+            self.spot_prices = prices.into_iter().map(|price| price.0).collect();
+        }
+    }
+
+    fn latest(&self) -> Observation {
+        self.observations.get(self.last_index).unwrap()
+    }
+
+    /// Returns observation `index` positions after the oldest one.
+    fn nth_oldest(&self, index: u64) -> Observation {
+        let len = self.observations.len();
+        self.observations
+            .get((self.last_index + 1 + index) % len)
+            .unwrap()
+    }
+
+    /// Accumulators extended from given observation to `timestamp` with the price holding since then.
+    fn extend(&self, observation: &Observation, price: &[U256], timestamp: u64) -> Vec<U256> {
+        let elapsed = U256::from(timestamp - observation.timestamp);
+        observation
+            .cumulatives
+            .iter()
+            .enumerate()
+            .map(|(index, cumulative)| {
+                let delta = price
+                    .get(index)
+                    .map(|price| price.overflowing_mul(elapsed).0)
+                    .unwrap_or_default();
+                U256(*cumulative).overflowing_add(delta).0
+            })
+            .collect()
+    }
+
+    /// Records the price held since the latest observation, then takes the new spot prices from pool.
+    fn update(&mut self, pool: &Pool) {
+        let now = now_sec();
+        let latest = self.latest();
+        if now > latest.timestamp {
+            let price: Vec<U256> = self.spot_prices.iter().map(|price| U256(*price)).collect();
+            let observation = Observation {
+                timestamp: now,
+                cumulatives: self
+                    .extend(&latest, &price, now)
+                    .into_iter()
+                    .map(|cumulative| cumulative.0)
+                    .collect(),
+            };
+            let len = self.observations.len();
+            if self.last_index + 1 == len && len < self.capacity as u64 {
+                self.observations.push(&observation);
+                self.last_index = len;
+            } else {
+                self.last_index = (self.last_index + 1) % len;
+                self.observations.replace(self.last_index, &observation);
+            }
+        }
+        self.set_spot_prices(pool);
+    }
+
+    /// Returns accumulators at given past timestamp, interpolating between observations.
+    fn cumulatives_at(&self, timestamp: u64) -> Result<Vec<U256>, &'static str> {
+        let latest = self.latest();
+        if timestamp >= latest.timestamp {
+            let price: Vec<U256> = self.spot_prices.iter().map(|price| U256(*price)).collect();
+            return Ok(self.extend(&latest, &price, timestamp));
+        }
+        if self.nth_oldest(0).timestamp > timestamp {
+            return Err(ERR97_ORACLE_HISTORY_TOO_SHORT);
+        }
+        // Find the first observation after timestamp.
+        let (mut low, mut high) = (0, self.observations.len() - 1);
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.nth_oldest(mid).timestamp <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let before = self.nth_oldest(low - 1);
+        let after = self.nth_oldest(low);
+        // The price was constant between two observations.
+        let elapsed = U256::from(after.timestamp - before.timestamp);
+        let price: Vec<U256> = before
+            .cumulatives
+            .iter()
+            .zip(after.cumulatives.iter())
+            .map(|(before, after)| U256(*after).overflowing_sub(U256(*before)).0 / elapsed)
+            .collect();
+        Ok(self.extend(&before, &price, timestamp))
+    }
+
+    fn get_twap(&self, token_in: usize, token_out: usize, window_sec: u64) -> Result<u128, &'static str> {
+        let now = now_sec();
+        let start = now.checked_sub(window_sec).ok_or(ERR97_ORACLE_HISTORY_TOO_SHORT)?;
+        let start_cumulatives = self.cumulatives_at(start)?;
+        let end_cumulatives = self.cumulatives_at(now)?;
+        let delta = |index: usize| {
+            end_cumulatives[index]
+                .overflowing_sub(start_cumulatives[index])
+                .0
+        };
+        // Both averages are divided by the same window, so it cancels out.
+        let (delta_in, delta_out) = (delta(token_in), delta(token_out));
+        if delta_out.is_zero() {
+            return Err(ERR98_TWAP_UNAVAILABLE);
+        }
+        let twap = delta_in
+            .checked_mul(U256::from(PRICE_PRECISION))
+            .ok_or(ERR98_TWAP_UNAVAILABLE)?
+            / delta_out;
+        if twap > U256::from(u128::MAX) {
+            return Err(ERR98_TWAP_UNAVAILABLE);
+        }
+        Ok(twap.as_u128())
+    }
+}
+
+impl Contract {
+    /// Records the pool's state in its oracle, if it has one. Called after every change of pool reserves.
+    /// Storage of new observations was paid when the capacity was set.
+    pub(crate) fn internal_update_oracle(&mut self, pool_id: u64, pool: &Pool) {
+        if let Some(mut oracle) = self.pool_oracles.get(&pool_id) {
+            oracle.update(pool);
+            self.pool_oracles.insert(&pool_id, &oracle);
+        }
+    }
+
+    pub(crate) fn internal_get_twap(
+        &self,
+        pool_id: u64,
+        token_in: &AccountId,
+        token_out: &AccountId,
+        window_sec: u32,
+    ) -> Result<u128, &'static str> {
+        let pool = self.pools.get(pool_id).ok_or("ERR_NO_POOL")?;
+        let oracle = self.pool_oracles.get(&pool_id).ok_or(ERR95_ORACLE_NOT_ENABLED)?;
+        let index = |token_id: &AccountId| {
+            pool.tokens()
+                .iter()
+                .position(|id| id == token_id)
+                .ok_or(ERR63_MISSING_TOKEN)
+        };
+        let (token_in, token_out) = (index(token_in)?, index(token_out)?);
+        if token_in == token_out || window_sec == 0 {
+            return Err(ERR98_TWAP_UNAVAILABLE);
+        }
+        oracle.get_twap(token_in, token_out, window_sec as u64)
+    }
+
+    /// Returns the least output of swapping given amount within `max_slippage_bps`
+    /// of the pool's TWAP over given window, at least 1.
+    pub(crate) fn internal_twap_min_amount_out(
+        &self,
+        pool_id: u64,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        window_sec: u32,
+        max_slippage_bps: u32,
+    ) -> Result<Balance, &'static str> {
+        let twap = self.internal_get_twap(pool_id, token_in, token_out, window_sec)?;
+        let min_amount_out = (U256::from(amount_in) * U256::from(twap) / U256::from(PRICE_PRECISION)
+            * U256::from(FEE_DIVISOR - max_slippage_bps)
+            / U256::from(FEE_DIVISOR))
+        .as_u128();
+        Ok(std::cmp::max(min_amount_out, 1))
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Enables price oracle on given pool, or grows the number of observations it keeps.
+    /// Anyone can call it, attached NEAR should cover storage of all `capacity` observations.
+    /// Observations are taken at most once per second on pool changes,
+    /// so the capacity bounds the longest window `get_twap` can serve on a busy pool.
+    #[payable]
+    pub fn set_pool_oracle_capacity(&mut self, pool_id: u64, capacity: u32) {
+        self.assert_contract_running();
+        assert!(capacity <= MAX_ORACLE_CAPACITY, "{}", ERR96_ILLEGAL_ORACLE_CAPACITY);
+        let prev_storage = env::storage_usage();
+        let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let (mut oracle, prev_unwritten) = match self.pool_oracles.get(&pool_id) {
+            Some(oracle) => {
+                assert!(capacity > oracle.capacity, "{}", ERR96_ILLEGAL_ORACLE_CAPACITY);
+                let unwritten = oracle.capacity as u64 - oracle.observations.len();
+                (oracle, unwritten)
+            }
+            None => {
+                assert!(capacity > 0, "{}", ERR96_ILLEGAL_ORACLE_CAPACITY);
+                (PoolOracle::new(pool_id, &pool), 0)
+            }
+        };
+        oracle.capacity = capacity;
+        let unwritten = capacity as u64 - oracle.observations.len();
+        self.pool_oracles.insert(&pool_id, &oracle);
+
+        // Observations not written yet are reserved here, and later added by swaps without charge.
+        let reserved_storage = (unwritten - prev_unwritten) * observation_storage(pool.tokens().len());
+        let storage_cost = (env::storage_usage().saturating_sub(prev_storage) + reserved_storage)
+            as Balance
+            * env::storage_byte_cost();
+        let refund = env::attached_deposit()
+            .checked_sub(storage_cost)
+            .unwrap_or_else(|| {
+                env::panic(
+                    format!(
+                        "ERR_STORAGE_DEPOSIT need {}, attatched {}",
+                        storage_cost, env::attached_deposit()
+                    ).as_bytes()
+                )
+            });
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        Event::PoolOracleCapacity {
+            pool_id,
+            account_id: &env::predecessor_account_id(),
+            capacity,
+        }
+        .emit();
+    }
+
+    /// Returns state of given pool's price oracle, None if not enabled.
+    pub fn get_pool_oracle(&self, pool_id: u64) -> Option<PoolOracleInfo> {
+        self.pool_oracles.get(&pool_id).map(|oracle| PoolOracleInfo {
+            capacity: oracle.capacity,
+            num_observations: oracle.observations.len(),
+            oldest_timestamp: oracle.nth_oldest(0).timestamp,
+            latest_timestamp: oracle.latest().timestamp,
+        })
+    }
+
+    /// Returns time-weighted average price of `token_in` in `token_out` over the last `window_sec` seconds,
+    /// as amount of `token_out` per unit of `token_in` multiplied by 10^18.
+    /// Prices are in the tokens' smallest units, quoting from the token with less decimals keeps more precision.
+    /// Panics if the pool has no oracle or its history doesn't cover the window.
+    pub fn get_twap(
+        &self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        token_out: ValidAccountId,
+        window_sec: u32,
+    ) -> U128 {
+        match self.internal_get_twap(pool_id, token_in.as_ref(), token_out.as_ref(), window_sec) {
+            Ok(twap) => U128(twap),
+            Err(err) => env::panic(err.as_bytes()),
+        }
+    }
+
+    /// Same as `get_twap` but returns None instead of panicking,
+    /// so contracts calling it can handle a missing price in their callback.
+    pub fn try_get_twap(
+        &self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        token_out: ValidAccountId,
+        window_sec: u32,
+    ) -> Option<U128> {
+        self.internal_get_twap(pool_id, token_in.as_ref(), token_out.as_ref(), window_sec)
+            .ok()
+            .map(U128)
+    }
+}
@@ -4,7 +4,8 @@ use near_sdk::json_types::WrappedTimestamp;
 use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
 
 use crate::*;
-use crate::utils::{FEE_DIVISOR, GAS_FOR_BASIC_OP};
+use crate::legacy::ContractV2;
+use crate::utils::{ext_self, FEE_DIVISOR, GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER};
 
 #[near_bindgen]
 impl Contract {
@@ -20,30 +21,30 @@ impl Contract {
     }
 
     /// Retrieve NEP-141 tokens that not mananged by contract to owner,
-    /// Caution: Must check that `amount <= total_amount_in_account - amount_managed_by_contract` before calling !!!
-    /// Returns promise of ft_transfer action.
+    /// Amount is limited to the surplus of contract's balance over the token liability,
+    /// which is checked in the callback after getting the balance.
+    /// First call requests the amount, the same call made again after `RETRIEVAL_DELAY_SEC`
+    /// sends it, see `exchange_callback_retrieve_unmanaged_token`.
+    /// Returns promise of the check, resolving into ft_transfer action on the second call.
     #[payable]
     pub fn retrieve_unmanaged_token(&mut self, token_id: ValidAccountId, amount: U128) -> Promise {
         self.assert_owner();
         assert_one_yocto();
         let token_id: AccountId = token_id.into();
-        let amount: u128 = amount.into();
-        assert!(amount > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
-        env::log(
-            format!(
-                "Going to retrieve token {} to owner, amount: {}",
-                &token_id, amount
-            )
-            .as_bytes(),
-        ); 
-        ext_fungible_token::ft_transfer(
-            self.owner_id.clone(),
-            U128(amount),
-            None,
+        assert!(amount.0 > 0, "{}", ERR29_ILLEGAL_WITHDRAW_AMOUNT);
+        ext_fungible_token::ft_balance_of(
+            env::current_account_id(),
             &token_id,
-            1,
-            env::prepaid_gas() - GAS_FOR_BASIC_OP,
+            0,
+            GAS_FOR_BASIC_OP,
         )
+        .then(ext_self::exchange_callback_retrieve_unmanaged_token(
+            token_id,
+            amount,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_BASIC_OP + GAS_FOR_FT_TRANSFER,
+        ))
     }
 
     /// Extend guardians. Only can be called by owner.
@@ -74,24 +75,25 @@ impl Contract {
                 // only owner can resume the contract
                 self.assert_owner();
             }
-            env::log(
-                format!(
-                    "Contract state changed from {} to {} by {}",
-                    self.state, state, env::predecessor_account_id()
-                )
-                .as_bytes(),
-            );       
+            Event::StateChanged {
+                old_state: &self.state,
+                new_state: &state,
+                changed_by: &env::predecessor_account_id(),
+            }
+            .emit();
             self.state = state;
         }
     }
 
     /// Extend whitelisted tokens with new tokens. Only can be called by owner.
+    /// Fetches metadata of the tokens into the registry.
     #[payable]
     pub fn extend_whitelisted_tokens(&mut self, tokens: Vec<ValidAccountId>) {
         assert!(self.is_owner_or_guardians(), "ERR_NOT_ALLOWED");
-        for token in tokens {
+        for token in tokens.iter() {
             self.whitelisted_tokens.insert(token.as_ref());
         }
+        self.internal_fetch_token_metadata(tokens.iter().map(|token| token.as_ref()));
     }
 
     /// Remove whitelisted token. Only can be called by owner.
@@ -102,6 +104,24 @@ impl Contract {
         }
     }
 
+    /// Deny tokens for given reason, blocking deposits, swaps into them and new pools with them.
+    /// Withdrawals and removing liquidity are still allowed. Only can be called by owner or guardians.
+    #[payable]
+    pub fn extend_denied_tokens(&mut self, tokens: Vec<ValidAccountId>, reason: String) {
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        for token in tokens {
+            self.denied_tokens.insert(token.as_ref(), &reason);
+        }
+    }
+
+    /// Remove tokens from the deny-list. Only can be called by owner or guardians.
+    pub fn remove_denied_tokens(&mut self, tokens: Vec<ValidAccountId>) {
+        assert!(self.is_owner_or_guardians(), "{}", ERR100_NOT_ALLOWED);
+        for token in tokens {
+            self.denied_tokens.remove(token.as_ref());
+        }
+    }
+
     pub fn modify_admin_fee(&mut self, exchange_fee: u32, referral_fee: u32) {
         self.assert_owner();
         assert!(exchange_fee + referral_fee <= FEE_DIVISOR, "ERR_ILLEGAL_FEE");
@@ -119,6 +139,11 @@ impl Contract {
         let ex_id = env::current_account_id();
         let owner_id = self.owner_id.clone();
         let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        assert!(
+            pool.share_balances(&ex_id).saturating_sub(shares.0) >= self.internal_creator_reserved_shares(pool_id),
+            "{}",
+            ERR37_CREATOR_SHARES_RESERVED
+        );
         let amounts = pool.remove_liquidity(
             &ex_id,
             shares.into(),
@@ -134,6 +159,14 @@ impl Contract {
             deposits.deposit(&tokens[i], amounts[i]);
         }
         self.internal_save_account(&owner_id, deposits);
+        self.internal_update_oracle(pool_id, &pool);
+        Event::RemoveLiquidity {
+            account_id: &ex_id,
+            pool_id,
+            amounts: amounts.into_iter().map(U128).collect(),
+            shares,
+        }
+        .emit();
     }
 
     /// to eventually change a stable pool's amp factor
@@ -180,14 +213,48 @@ impl Contract {
             || self.guardians.contains(&env::predecessor_account_id())
     }
 
-    /// Migration function from v2 to v2.
+    /// Migration function from v1.4.x to v1.5.0.
     /// For next version upgrades, change this function.
     #[init(ignore_state)]
     // [AUDIT_09]
     #[private]
     pub fn migrate() -> Self {
-        let contract: Contract = env::state_read().expect("ERR_NOT_INITIALIZED");
-        contract
+        let state = env::storage_read(b"STATE").expect("ERR_NOT_INITIALIZED");
+        // Upgrading to the same code, state is already in current layout.
+        if let Ok(contract) = Contract::try_from_slice(&state) {
+            return contract;
+        }
+        let contract = ContractV2::try_from_slice(&state).expect("ERR_NOT_INITIALIZED");
+        // Failed returns used to be credited to owner's inner account, mixed with its own balances,
+        // so they can't be told apart here. Owner returns them by hand, new ones go to `lostfound`.
+        Contract {
+            owner_id: contract.owner_id,
+            exchange_fee: contract.exchange_fee,
+            referral_fee: contract.referral_fee,
+            pools: contract.pools,
+            accounts: contract.accounts,
+            whitelisted_tokens: contract.whitelisted_tokens,
+            guardians: contract.guardians,
+            state: contract.state,
+            mft_allowances: LookupMap::new(StorageKey::MftAllowances),
+            trading_delegations: LookupMap::new(StorageKey::TradingDelegations),
+            lostfound: UnorderedMap::new(StorageKey::LostFound),
+            token_liabilities: LookupMap::new(StorageKey::TokenLiabilities),
+            liability_seeded_tokens: Some(LookupSet::new(StorageKey::LiabilitySeededTokens)),
+            account_pools: LookupMap::new(StorageKey::AccountPools),
+            pool_oracles: LookupMap::new(StorageKey::PoolOracles),
+            pool_stats: LookupMap::new(StorageKey::PoolStats),
+            pool_bond_config: PoolBondConfig::default(),
+            pool_bonds: LookupMap::new(StorageKey::PoolBonds),
+            token_metadata: LookupMap::new(StorageKey::TokenMetadata),
+            denied_tokens: UnorderedMap::new(StorageKey::DeniedTokens),
+            token_migrations: LookupMap::new(StorageKey::TokenMigrations),
+            pool_creators: LookupMap::new(StorageKey::PoolCreators),
+            creator_fee_cap: 0,
+            fee_harvest_config: None,
+            pending_retrievals: LookupMap::new(StorageKey::PendingRetrievals),
+            deactivated_pools: UnorderedSet::new(StorageKey::DeactivatedPools),
+        }
     }
 }
 
@@ -1,10 +1,15 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{AccountId, Balance};
+use near_sdk::{env, AccountId, Balance};
 
 use crate::admin_fee::AdminFees;
+use crate::errors::{ErrorCode, ERR88_NOT_SUPPORTED};
 use crate::simple_pool::SimplePool;
 use crate::stable_swap::StableSwapPool;
-use crate::utils::SwapVolume;
+use crate::utils::{SwapOutcome, SwapVolume, PRICE_PRECISION, U256};
+
+/// Scale of prices quoted in the pool's first token.
+/// High enough to price a token with many more decimals than the first one.
+pub const SPOT_PRICES_PRECISION: u128 = 1_000_000_000_000_000_000_000_000_000_000_000_000;
 
 /// Generic Pool, providing wrapper around different implementations of swap pools.
 /// Allows to add new types of pools just by adding extra item in the enum without needing to migrate the storage.
@@ -31,6 +36,20 @@ impl Pool {
         }
     }
 
+    /// Replaces given token with a new token account, returns the pool's reserve of it.
+    /// Stable pools require the decimals of the new token, None if unknown, and fail without them.
+    pub fn migrate_token(
+        &mut self,
+        old_token_id: &AccountId,
+        new_token_id: &AccountId,
+        new_decimals: Option<u8>,
+    ) -> Result<Balance, ErrorCode> {
+        match self {
+            Pool::SimplePool(pool) => Ok(pool.migrate_token(old_token_id, new_token_id)),
+            Pool::StableSwapPool(pool) => pool.migrate_token(old_token_id, new_token_id, new_decimals),
+        }
+    }
+
     /// Adds liquidity into underlying pool.
     /// Updates amounts to amount kept in the pool.
     pub fn add_liquidity(
@@ -49,11 +68,11 @@ impl Pool {
         sender_id: &AccountId,
         amounts: &Vec<Balance>,
         min_shares: Balance,
-        admin_fee: AdminFees,
+        admin_fee: &AdminFees,
     ) -> Balance {
         match self {
             Pool::SimplePool(_) => unimplemented!(),
-            Pool::StableSwapPool(pool) => pool.add_liquidity(sender_id, amounts, min_shares, &admin_fee),
+            Pool::StableSwapPool(pool) => pool.add_liquidity(sender_id, amounts, min_shares, admin_fee),
         }
     }
 
@@ -78,12 +97,30 @@ impl Pool {
         sender_id: &AccountId,
         amounts: Vec<Balance>,
         max_burn_shares: Balance,
-        admin_fee: AdminFees,
+        admin_fee: &AdminFees,
     ) -> Balance {
         match self {
             Pool::SimplePool(_) => unimplemented!(),
             Pool::StableSwapPool(pool) => {
-                pool.remove_liquidity_by_tokens(sender_id, amounts, max_burn_shares, &admin_fee)
+                pool.remove_liquidity_by_tokens(sender_id, amounts, max_burn_shares, admin_fee)
+            }
+        }
+    }
+
+    /// Burns given shares of sender for one of the pool tokens.
+    /// Only stable pools support it.
+    pub fn remove_liquidity_one_coin(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        token_out: &AccountId,
+        min_amount: Balance,
+        admin_fee: &AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(_) => env::panic(ERR88_NOT_SUPPORTED.as_bytes()),
+            Pool::StableSwapPool(pool) => {
+                pool.remove_liquidity_one_coin(sender_id, shares, token_out, min_amount, admin_fee)
             }
         }
     }
@@ -102,6 +139,15 @@ impl Pool {
         }
     }
 
+    /// Returns the part of `amount_in` to swap before adding both tokens as liquidity.
+    /// Only simple pools support it.
+    pub fn zap_swap_amount(&self, token_in: &AccountId, amount_in: Balance) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.zap_swap_amount(token_in, amount_in),
+            Pool::StableSwapPool(_) => env::panic(ERR88_NOT_SUPPORTED.as_bytes()),
+        }
+    }
+
     /// Return share decimal.
     pub fn get_share_decimal(&self) -> u8 {
         match self {
@@ -127,28 +173,76 @@ impl Pool {
     }
 
     /// Returns given pool's share price in precision 1e8.
-    pub fn get_share_price(&self) -> u128 {
+    /// Simple pools price it in given token, the first one if None,
+    /// stable pools in their comparable unit, ignoring given token.
+    pub fn get_share_price(&self, token_id: Option<&AccountId>) -> u128 {
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(pool) => {
+                pool.get_share_price(token_id.unwrap_or(&pool.token_account_ids[0]))
+            }
             Pool::StableSwapPool(pool) => pool.get_share_price(),
         }
     }
 
-    /// Swaps given number of token_in for token_out and returns received amount.
+    /// Returns price of each token in the first token, scaled by `precision`.
+    /// None if the pool has no liquidity.
+    pub fn spot_prices(&self, precision: U256) -> Option<Vec<U256>> {
+        match self {
+            Pool::SimplePool(pool) => pool.spot_prices(precision),
+            Pool::StableSwapPool(pool) => pool.spot_prices(precision),
+        }
+    }
+
+    /// Returns amount of token_out per unit of token_in at current reserves, scaled by `PRICE_PRECISION`.
+    /// None if the pool has no liquidity or the price doesn't fit.
+    pub fn spot_price(&self, token_in: &AccountId, token_out: &AccountId) -> Option<u128> {
+        let index = |token_id: &AccountId| self.tokens().iter().position(|id| id == token_id);
+        let (token_in, token_out) = (index(token_in)?, index(token_out)?);
+        let prices = self.spot_prices(U256::from(SPOT_PRICES_PRECISION))?;
+        if prices[token_out].is_zero() {
+            return None;
+        }
+        let price = prices[token_in].checked_mul(U256::from(PRICE_PRECISION))? / prices[token_out];
+        if price > U256::from(u128::MAX) {
+            return None;
+        }
+        Some(price.as_u128())
+    }
+
+    /// Returns the error `swap` would fail with for given arguments, if any.
+    pub fn check_swap(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> Result<(), &'static str> {
+        match self {
+            Pool::SimplePool(pool) => {
+                pool.check_swap(token_in, amount_in, token_out, min_amount_out)
+            }
+            Pool::StableSwapPool(pool) => {
+                pool.check_swap(token_in, amount_in, token_out, min_amount_out, admin_fee)
+            }
+        }
+    }
+
+    /// Swaps given number of token_in for token_out and returns received amount with the LP fee.
     pub fn swap(
         &mut self,
         token_in: &AccountId,
         amount_in: Balance,
         token_out: &AccountId,
         min_amount_out: Balance,
-        admin_fee: AdminFees,
-    ) -> Balance {
+        admin_fee: &AdminFees,
+    ) -> SwapOutcome {
         match self {
             Pool::SimplePool(pool) => {
-                pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
+                pool.swap_detailed(token_in, amount_in, token_out, min_amount_out, admin_fee)
             }
             Pool::StableSwapPool(pool) => {
-                pool.swap(token_in, amount_in, token_out, min_amount_out, &admin_fee)
+                pool.swap_detailed(token_in, amount_in, token_out, min_amount_out, admin_fee)
             }
         }
     }
@@ -167,6 +261,20 @@ impl Pool {
         }
     }
 
+    pub fn share_unregister(&mut self, account_id: &AccountId) {
+        match self {
+            Pool::SimplePool(pool) => pool.share_unregister(account_id),
+            Pool::StableSwapPool(pool) => pool.share_unregister(account_id),
+        }
+    }
+
+    pub fn share_has_registered(&self, account_id: &AccountId) -> bool {
+        match self {
+            Pool::SimplePool(pool) => pool.share_has_registered(account_id),
+            Pool::StableSwapPool(pool) => pool.share_has_registered(account_id),
+        }
+    }
+
     pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
         match self {
             Pool::SimplePool(pool) => pool.share_transfer(sender_id, receiver_id, amount),
@@ -187,17 +295,33 @@ impl Pool {
         fees: &AdminFees,
     ) -> Balance {
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(pool) => pool.predict_add_liquidity(amounts).0,
             Pool::StableSwapPool(pool) => pool.predict_add_stable_liquidity(amounts, fees),
         }
     }
 
+    /// Returns number of shares adding given amounts would mint, and amounts refunded.
+    /// Stable pools keep all of the amounts.
+    pub fn predict_add_liquidity(
+        &self,
+        amounts: &Vec<Balance>,
+        fees: &AdminFees,
+    ) -> (Balance, Vec<Balance>) {
+        match self {
+            Pool::SimplePool(pool) => pool.predict_add_liquidity(amounts),
+            Pool::StableSwapPool(pool) => (
+                pool.predict_add_stable_liquidity(amounts, fees),
+                vec![0; amounts.len()],
+            ),
+        }
+    }
+
     pub fn predict_remove_liquidity(
         &self,
         shares: Balance,
     ) -> Vec<Balance> {
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(pool) => pool.predict_remove_liquidity(shares),
             Pool::StableSwapPool(pool) => pool.predict_remove_liquidity(shares),
         }
     }
@@ -208,8 +332,74 @@ impl Pool {
         fees: &AdminFees,
     ) -> Balance {
         match self {
-            Pool::SimplePool(_) => unimplemented!(),
+            Pool::SimplePool(pool) => pool.predict_remove_liquidity_by_tokens(amounts),
             Pool::StableSwapPool(pool) => pool.predict_remove_liquidity_by_tokens(amounts, fees),
         }
     }
+
+    /// Returns amount of given token `remove_liquidity_one_coin` would give for the shares.
+    /// Only stable pools support it.
+    pub fn predict_remove_liquidity_one_coin(
+        &self,
+        shares: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(_) => env::panic(ERR88_NOT_SUPPORTED.as_bytes()),
+            Pool::StableSwapPool(pool) => pool.predict_remove_liquidity_one_coin(shares, token_out, fees),
+        }
+    }
+
+    /// Same as `get_return`, returning an error instead of panicking.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Result<Balance, ErrorCode> {
+        match self {
+            Pool::SimplePool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.try_get_return(token_in, amount_in, token_out, fees),
+        }
+    }
+
+    pub fn try_get_share_price(&self, token_id: Option<&AccountId>) -> Result<u128, ErrorCode> {
+        match self {
+            Pool::SimplePool(pool) => {
+                pool.try_get_share_price(token_id.unwrap_or(&pool.token_account_ids[0]))
+            }
+            Pool::StableSwapPool(pool) => pool.try_get_share_price(),
+        }
+    }
+
+    pub fn try_predict_add_stable_liquidity(
+        &self,
+        amounts: &[Balance],
+        fees: &AdminFees,
+    ) -> Result<Balance, ErrorCode> {
+        match self {
+            Pool::SimplePool(pool) => pool.try_predict_add_liquidity(amounts).map(|(shares, _)| shares),
+            Pool::StableSwapPool(pool) => pool.try_predict_add_stable_liquidity(amounts, fees),
+        }
+    }
+
+    pub fn try_predict_remove_liquidity(&self, shares: Balance) -> Result<Vec<Balance>, ErrorCode> {
+        match self {
+            Pool::SimplePool(pool) => pool.try_predict_remove_liquidity(shares),
+            Pool::StableSwapPool(pool) => pool.try_predict_remove_liquidity(shares),
+        }
+    }
+
+    pub fn try_predict_remove_liquidity_by_tokens(
+        &self,
+        amounts: &[Balance],
+        fees: &AdminFees,
+    ) -> Result<Balance, ErrorCode> {
+        match self {
+            Pool::SimplePool(pool) => pool.try_predict_remove_liquidity_by_tokens(amounts),
+            Pool::StableSwapPool(pool) => pool.try_predict_remove_liquidity_by_tokens(amounts, fees),
+        }
+    }
 }
@@ -0,0 +1,137 @@
+//! Share of the exchange fee for the creators of pools.
+//!
+//! The account that created a pool can opt into a share of the exchange fee it generates,
+//! up to the cap set by owner. The LP shares minted to the exchange as admin fee are split
+//! as they are minted, and the creator's part is kept under the exchange until claimed.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::utils::{FEE_DIVISOR, U256};
+use crate::*;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PoolCreator {
+    pub creator_id: AccountId,
+    /// Part of the exchange fee claimed by the creator, in bps, applied up to the cap.
+    pub fee_share: u32,
+    /// Shares earned by the creator, held by the exchange until claimed.
+    pub unclaimed_shares: U128,
+}
+
+impl Contract {
+    pub(crate) fn internal_set_pool_creator(&mut self, pool_id: u64, creator_id: &AccountId) {
+        self.pool_creators.insert(
+            &pool_id,
+            &PoolCreator {
+                creator_id: creator_id.clone(),
+                fee_share: 0,
+                unclaimed_shares: U128(0),
+            },
+        );
+    }
+
+    /// Emits fee events for the shares minted since `prev_shares` were taken,
+    /// and sets aside the creator's part of the exchange's.
+    pub(crate) fn internal_distribute_fee_shares(
+        &mut self,
+        pool_id: u64,
+        pool: &Pool,
+        fees: &AdminFees,
+        prev_shares: (Balance, Balance),
+    ) {
+        fees.emit_minted_shares(pool_id, pool, prev_shares);
+        let mut creator = match self.pool_creators.get(&pool_id) {
+            Some(creator) if creator.fee_share > 0 => creator,
+            _ => return,
+        };
+        let minted = pool.share_balances(&fees.exchange_id).saturating_sub(prev_shares.0);
+        let fee_share = std::cmp::min(creator.fee_share, self.creator_fee_cap);
+        let creator_shares = U256::from(minted) * U256::from(fee_share) / U256::from(FEE_DIVISOR);
+        if creator_shares.is_zero() {
+            return;
+        }
+        creator.unclaimed_shares.0 += creator_shares.as_u128();
+        self.pool_creators.insert(&pool_id, &creator);
+    }
+
+    /// Exchange shares of given pool that belong to its creator.
+    pub(crate) fn internal_creator_reserved_shares(&self, pool_id: u64) -> Balance {
+        self.pool_creators
+            .get(&pool_id)
+            .map(|creator| creator.unclaimed_shares.0)
+            .unwrap_or(0)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the part of the exchange fee the creator of given pool takes, in bps,
+    /// from the fees generated afterwards. Only can be called by the creator.
+    pub fn set_pool_creator_fee(&mut self, pool_id: u64, fee_share: u32) {
+        let mut creator = self.pool_creators.get(&pool_id).expect(ERR100_NOT_ALLOWED);
+        assert_eq!(env::predecessor_account_id(), creator.creator_id, "{}", ERR100_NOT_ALLOWED);
+        assert!(fee_share <= self.creator_fee_cap, "{}", ERR38_ILLEGAL_CREATOR_FEE);
+        creator.fee_share = fee_share;
+        self.pool_creators.insert(&pool_id, &creator);
+    }
+
+    /// Moves the LP shares earned by the creator of given pool to its account.
+    /// If the creator is not registered in the pool, it gets registered, paid by attached NEAR,
+    /// the rest is refunded. Returns the shares claimed.
+    #[payable]
+    pub fn claim_pool_creator_fee(&mut self, pool_id: u64) -> U128 {
+        assert!(
+            env::attached_deposit() > 0,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let mut creator = self.pool_creators.get(&pool_id).expect(ERR100_NOT_ALLOWED);
+        assert_eq!(env::predecessor_account_id(), creator.creator_id, "{}", ERR100_NOT_ALLOWED);
+        let mut pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let exchange_id = env::current_account_id();
+        let shares = std::cmp::min(creator.unclaimed_shares.0, pool.share_balances(&exchange_id));
+        if shares > 0 {
+            if !pool.share_has_registered(&creator.creator_id) {
+                pool.share_register(&creator.creator_id);
+            }
+            pool.share_transfer(&exchange_id, &creator.creator_id, shares);
+            self.pools.replace(pool_id, &pool);
+            Event::CreatorFeeClaimed {
+                pool_id,
+                creator_id: &creator.creator_id,
+                shares: U128(shares),
+            }
+            .emit();
+        }
+        creator.unclaimed_shares = U128(0);
+        self.pool_creators.insert(&pool_id, &creator);
+        self.internal_check_storage(prev_storage);
+        if shares > 0 {
+            self.internal_index_account_pool(&creator.creator_id, pool_id);
+        }
+        U128(shares)
+    }
+
+    /// Sets the max part of the exchange fee, in bps, that pool creators can take. Only can be called by owner.
+    pub fn set_creator_fee_cap(&mut self, cap: u32) {
+        self.assert_owner();
+        assert!(cap <= FEE_DIVISOR, "{}", ERR38_ILLEGAL_CREATOR_FEE);
+        self.creator_fee_cap = cap;
+    }
+
+    pub fn get_creator_fee_cap(&self) -> u32 {
+        self.creator_fee_cap
+    }
+
+    /// Returns the creator of given pool, None for pools created by owner or guardians
+    /// with `add_stable_swap_pool`, or before creators were recorded.
+    pub fn get_pool_creator(&self, pool_id: u64) -> Option<PoolCreator> {
+        self.pool_creators.get(&pool_id)
+    }
+}
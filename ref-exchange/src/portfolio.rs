@@ -0,0 +1,148 @@
+//! Per-account index of pools where the account has a share entry,
+//! so positions can be listed without querying every pool.
+//!
+//! Storage of the index is taken out of the account's storage deposit and goes back to it
+//! when an entry is removed. Accounts without an exchange account or without enough
+//! available storage are not indexed, same as share entries created before the index existed,
+//! until they sync it.
+
+use std::collections::HashMap;
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::utils::{FEE_DIVISOR, U256};
+use crate::*;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct LpPosition {
+    pub pool_id: u64,
+    pub shares: U128,
+    /// Token amounts the shares would get back when removed, in pool tokens sequence.
+    pub amounts: Vec<U128>,
+    /// Part of pool total shares, in bps.
+    pub share_bps: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct AccountPortfolio {
+    pub deposits: HashMap<AccountId, U128>,
+    pub positions: Vec<LpPosition>,
+}
+
+impl Contract {
+    /// Records that account has a share entry in given pool, paid from account's storage deposit.
+    /// Skipped if account is not registered or has not enough storage available.
+    pub(crate) fn internal_index_account_pool(&mut self, account_id: &AccountId, pool_id: u64) {
+        let mut account = match self.internal_get_account(account_id) {
+            Some(account) => account,
+            None => return,
+        };
+        let prev_pool_ids = self.account_pools.get(account_id);
+        let mut pool_ids = prev_pool_ids.clone().unwrap_or_default();
+        if let Err(index) = pool_ids.binary_search(&pool_id) {
+            let prev_storage = env::storage_usage();
+            pool_ids.insert(index, pool_id);
+            self.account_pools.insert(account_id, &pool_ids);
+            let storage_cost = (env::storage_usage() - prev_storage) as Balance
+                * env::storage_byte_cost();
+            if account.storage_available() < storage_cost {
+                match prev_pool_ids {
+                    Some(prev_pool_ids) => self.account_pools.insert(account_id, &prev_pool_ids),
+                    None => self.account_pools.remove(account_id),
+                };
+                log!("Pool {} not indexed for {}: {}", pool_id, account_id, ERR11_INSUFFICIENT_STORAGE);
+                return;
+            }
+            account.near_amount -= storage_cost;
+            self.internal_save_account(account_id, account);
+        }
+    }
+
+    /// Removes given pool from account's index.
+    /// Freed storage goes to account's storage deposit if it has an account, otherwise is sent back to it.
+    pub(crate) fn internal_unindex_account_pool(&mut self, account_id: &AccountId, pool_id: u64) {
+        let mut pool_ids = self.account_pools.get(account_id).unwrap_or_default();
+        if let Ok(index) = pool_ids.binary_search(&pool_id) {
+            let prev_storage = env::storage_usage();
+            pool_ids.remove(index);
+            if pool_ids.is_empty() {
+                self.account_pools.remove(account_id);
+            } else {
+                self.account_pools.insert(account_id, &pool_ids);
+            }
+            let refund = (prev_storage - env::storage_usage()) as Balance
+                * env::storage_byte_cost();
+            if let Some(mut account) = self.internal_get_account(account_id) {
+                account.near_amount += refund;
+                self.internal_save_account(account_id, account);
+            } else if refund > 0 {
+                Promise::new(account_id.clone()).transfer(refund);
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Brings caller's pool index in line with given pools, for share entries
+    /// that were not indexed. Added entries are paid from caller's storage deposit.
+    #[payable]
+    pub fn sync_account_pools(&mut self, pool_ids: Vec<u64>) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.internal_unwrap_account(&account_id);
+        for pool_id in pool_ids {
+            let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+            if pool.share_has_registered(&account_id) {
+                self.internal_index_account_pool(&account_id, pool_id);
+            } else {
+                self.internal_unindex_account_pool(&account_id, pool_id);
+            }
+        }
+    }
+
+    /// Returns ids of pools where given account has LP token registered, including zero balances.
+    pub fn get_account_registered_pools(&self, account_id: ValidAccountId) -> Vec<u64> {
+        self.account_pools.get(account_id.as_ref()).unwrap_or_default()
+    }
+
+    /// Returns deposits of given account and its LP positions with the underlying token amounts.
+    pub fn get_account_portfolio(&self, account_id: ValidAccountId) -> AccountPortfolio {
+        let positions = self
+            .account_pools
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|pool_id| {
+                let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+                let shares = pool.share_balances(account_id.as_ref());
+                if shares == 0 {
+                    return None;
+                }
+                let share_bps = (U256::from(shares) * U256::from(FEE_DIVISOR)
+                    / U256::from(pool.share_total_balance()))
+                .as_u32();
+                Some(LpPosition {
+                    pool_id,
+                    shares: U128(shares),
+                    amounts: pool
+                        .predict_remove_liquidity(shares)
+                        .into_iter()
+                        .map(U128)
+                        .collect(),
+                    share_bps,
+                })
+            })
+            .collect();
+        AccountPortfolio {
+            deposits: self.get_deposits(account_id),
+            positions,
+        }
+    }
+}
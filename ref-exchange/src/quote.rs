@@ -0,0 +1,172 @@
+//! Detailed swap quotes, computed by running the swaps on in-memory copies of the pools.
+//!
+//! Swaps are run without admin fees, which only mint shares and don't change reserves or
+//! amounts out, so nothing is written to storage. Exchange and referral parts of the fee
+//! are then split from the total fee the way each pool kind does it.
+
+use std::collections::HashMap;
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance};
+
+use crate::utils::{FEE_DIVISOR, PRICE_PRECISION, U256};
+use crate::*;
+
+/// Breakdown of one swap step.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct SwapDetail {
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub amount_in: U128,
+    pub token_out: AccountId,
+    pub amount_out: U128,
+    /// Token the fees below are charged in, token_in for simple pools and token_out for stable pools.
+    pub fee_token: AccountId,
+    pub total_fee: U128,
+    /// Part of total fee kept by liquidity providers.
+    pub lp_fee: U128,
+    /// Part of total fee going to the exchange.
+    pub admin_fee: U128,
+    /// Part of total fee going to the referral, 0 if none or not registered in the pool.
+    pub referral_fee: U128,
+    /// Amount of token_out per unit of token_in, scaled by 10^18, None if not available.
+    pub spot_price_before: Option<U128>,
+    pub spot_price_after: Option<U128>,
+    /// Shortfall of the amount out, fees excluded, against the spot price before the swap.
+    pub price_impact_bps: u32,
+    /// Current amplification of stable pools.
+    pub amp: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct ReturnDetail {
+    pub amount_in: U128,
+    pub amount_out: U128,
+    pub steps: Vec<SwapDetail>,
+}
+
+impl Contract {
+    /// Runs a swap on the in-memory copy of the pool in `pools`, loading it on first use.
+    pub(crate) fn internal_quote_swap(
+        &self,
+        pools: &mut HashMap<u64, Pool>,
+        pool_id: u64,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        referral_id: &Option<AccountId>,
+    ) -> SwapDetail {
+        let pool = pools
+            .entry(pool_id)
+            .or_insert_with(|| self.pools.get(pool_id).expect("ERR_NO_POOL"));
+        let spot_price_before = pool.spot_price(token_in, token_out);
+        let outcome = pool.swap(token_in, amount_in, token_out, 0, &AdminFees::zero());
+        // Without admin fees, all of the fee is reported as LP fee.
+        let total_fee = outcome.lp_fee;
+        let referral_fee_bps = match referral_id {
+            Some(referral_id) if pool.share_has_registered(referral_id) => self.referral_fee,
+            _ => 0,
+        };
+        let part = |amount: Balance, bps: u32| {
+            (U256::from(amount) * U256::from(bps) / U256::from(FEE_DIVISOR)).as_u128()
+        };
+        let (admin_fee, referral_fee) = match pool {
+            Pool::SimplePool(_) => (
+                part(total_fee, self.exchange_fee),
+                part(total_fee, referral_fee_bps),
+            ),
+            // Stable pools charge both parts together and give all of it to exchange
+            // if referral is not registered.
+            Pool::StableSwapPool(_) => {
+                let admin_fees = part(total_fee, self.exchange_fee + self.referral_fee);
+                let referral_fee = if referral_fee_bps > 0 {
+                    admin_fees * self.referral_fee as u128
+                        / (self.referral_fee + self.exchange_fee) as u128
+                } else {
+                    0
+                };
+                (admin_fees - referral_fee, referral_fee)
+            }
+        };
+        let fee_token = pool.tokens()[outcome.fee_token].clone();
+        let (amount_in_no_fee, amount_out_no_fee) = if &fee_token == token_in {
+            (amount_in - total_fee, outcome.amount_out)
+        } else {
+            (amount_in, outcome.amount_out + total_fee)
+        };
+        let price_impact_bps = spot_price_before
+            .map(|spot_price| {
+                let expected = U256::from(amount_in_no_fee) * U256::from(spot_price)
+                    / U256::from(PRICE_PRECISION);
+                if expected > U256::from(amount_out_no_fee) {
+                    ((expected - U256::from(amount_out_no_fee)) * U256::from(FEE_DIVISOR)
+                        / expected)
+                        .as_u32()
+                } else {
+                    0
+                }
+            })
+            .unwrap_or(0);
+        SwapDetail {
+            pool_id,
+            token_in: token_in.clone(),
+            amount_in: U128(amount_in),
+            token_out: token_out.clone(),
+            amount_out: U128(outcome.amount_out),
+            fee_token,
+            total_fee: U128(total_fee),
+            lp_fee: U128(total_fee - admin_fee - referral_fee),
+            admin_fee: U128(admin_fee),
+            referral_fee: U128(referral_fee),
+            spot_price_before: spot_price_before.map(U128),
+            spot_price_after: pool.spot_price(token_in, token_out).map(U128),
+            price_impact_bps,
+            amp: match pool {
+                Pool::SimplePool(_) => None,
+                Pool::StableSwapPool(pool) => Some(pool.get_amp()),
+            },
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns a full quote of given swap actions, each step seeing the pools as left by the previous ones.
+    /// Same as `swap`, amount_in of a step can be None to take the output of the previous step.
+    /// min_amount_out is not checked.
+    pub fn get_return_detail(
+        &self,
+        actions: Vec<SwapAction>,
+        referral_id: Option<ValidAccountId>,
+    ) -> ReturnDetail {
+        assert_ne!(actions.len(), 0, "ERR_AT_LEAST_ONE_SWAP");
+        let referral_id = referral_id.map(|r| r.into());
+        let mut pools = HashMap::new();
+        let mut steps: Vec<SwapDetail> = vec![];
+        for action in actions {
+            let amount_in = action
+                .amount_in
+                .map(|amount| amount.0)
+                .or_else(|| steps.last().map(|step| step.amount_out.0))
+                .expect(ERR41_WRONG_ACTION_RESULT);
+            steps.push(self.internal_quote_swap(
+                &mut pools,
+                action.pool_id,
+                &action.token_in,
+                amount_in,
+                &action.token_out,
+                &referral_id,
+            ));
+        }
+        ReturnDetail {
+            amount_in: steps[0].amount_in,
+            amount_out: steps[steps.len() - 1].amount_out,
+            steps,
+        }
+    }
+}
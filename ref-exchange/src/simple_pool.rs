@@ -8,10 +8,11 @@ use crate::StorageKey;
 use crate::admin_fee::AdminFees;
 
 use crate::errors::{
-    ERR13_LP_NOT_REGISTERED, ERR14_LP_ALREADY_REGISTERED, ERR31_ZERO_AMOUNT, ERR32_ZERO_SHARES,
+    ErrorCode, ERR13_LP_NOT_REGISTERED, ERR14_LP_ALREADY_REGISTERED, ERR15_NON_ZERO_LP_SHARES,
+    ERR32_ZERO_SHARES, ERR34_INSUFFICIENT_LP_SHARES, ERR80_ILLEGAL_TOKEN_MIGRATION, ERR87_EMPTY_POOL,
 };
 use crate::utils::{
-    add_to_collection, integer_sqrt, SwapVolume, FEE_DIVISOR, INIT_SHARES_SUPPLY, U256,
+    add_to_collection, integer_sqrt, SwapOutcome, SwapVolume, FEE_DIVISOR, INIT_SHARES_SUPPLY, U256,
 };
 
 const NUM_TOKENS: usize = 2;
@@ -77,6 +78,13 @@ impl SimplePool {
         self.shares.insert(account_id, &0);
     }
 
+    /// Removes share entry of given account, freeing its storage.
+    /// Fails if the account still has shares.
+    pub fn share_unregister(&mut self, account_id: &AccountId) {
+        let balance = self.shares.remove(account_id).expect(ERR13_LP_NOT_REGISTERED);
+        assert_eq!(balance, 0, "{}", ERR15_NON_ZERO_LP_SHARES);
+    }
+
     /// Transfers shares from predecessor to receiver.
     pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
         let balance = self.shares.get(&sender_id).expect("ERR_NO_SHARES");
@@ -97,6 +105,109 @@ impl SimplePool {
         self.shares.get(account_id).unwrap_or_default()
     }
 
+    /// Returns if given account has an entry in shares, even with 0 balance.
+    pub fn share_has_registered(&self, account_id: &AccountId) -> bool {
+        self.shares.contains_key(account_id)
+    }
+
+    /// Returns token amounts that given number of shares would get back from `remove_liquidity`.
+    /// Zeros if pool has no shares.
+    pub fn predict_remove_liquidity(&self, shares: Balance) -> Vec<Balance> {
+        if self.shares_total_supply == 0 {
+            return vec![0; self.amounts.len()];
+        }
+        self.amounts
+            .iter()
+            .map(|amount| {
+                (U256::from(*amount) * U256::from(shares) / U256::from(self.shares_total_supply))
+                    .as_u128()
+            })
+            .collect()
+    }
+
+    /// Same as `predict_remove_liquidity`, returning an error instead of panicking.
+    pub fn try_predict_remove_liquidity(&self, shares: Balance) -> Result<Vec<Balance>, ErrorCode> {
+        if shares > self.shares_total_supply || self.shares_total_supply == 0 {
+            return Err(ErrorCode::E34InsufficientLpShares);
+        }
+        Ok(self.predict_remove_liquidity(shares))
+    }
+
+    /// Returns number of shares to burn with `remove_liquidity` to get at least given amounts.
+    pub fn predict_remove_liquidity_by_tokens(&self, amounts: &[Balance]) -> Balance {
+        assert_eq!(
+            amounts.len(),
+            self.token_account_ids.len(),
+            "ERR_WRONG_TOKEN_COUNT"
+        );
+        let mut shares = U256::zero();
+        for i in 0..self.token_account_ids.len() {
+            if amounts[i] == 0 {
+                continue;
+            }
+            assert!(self.amounts[i] > 0, "{}", ERR34_INSUFFICIENT_LP_SHARES);
+            // Rounded up, so the amount out rounded down is not less than asked.
+            let needed = (U256::from(amounts[i]) * U256::from(self.shares_total_supply)
+                + U256::from(self.amounts[i] - 1))
+                / U256::from(self.amounts[i]);
+            shares = std::cmp::max(shares, needed);
+        }
+        assert!(
+            shares <= U256::from(self.shares_total_supply),
+            "{}",
+            ERR34_INSUFFICIENT_LP_SHARES
+        );
+        shares.as_u128()
+    }
+
+    /// Same as `predict_remove_liquidity_by_tokens`, returning an error instead of panicking.
+    pub fn try_predict_remove_liquidity_by_tokens(&self, amounts: &[Balance]) -> Result<Balance, ErrorCode> {
+        if amounts.len() != self.token_account_ids.len() {
+            return Err(ErrorCode::E64TokensCountIllegal);
+        }
+        if amounts.iter().zip(self.amounts.iter()).any(|(amount, reserve)| amount > reserve) {
+            return Err(ErrorCode::E34InsufficientLpShares);
+        }
+        Ok(self.predict_remove_liquidity_by_tokens(amounts))
+    }
+
+    /// Returns value of a share in given token, as amount of the token per share, both in their smallest units,
+    /// with 1e8 precision. Reserves are valued at the pool's spot price, and as an equal-weight constant product
+    /// pool holds reserves of equal value at its spot price, they are worth the token's reserve times
+    /// the number of tokens. It is not a market price: a pool off the market is valued at its own price.
+    pub fn get_share_price(&self, token_id: &AccountId) -> u128 {
+        (U256::from(self.amounts[self.token_index(token_id)])
+            * U256::from(self.token_account_ids.len())
+            * U256::from(100000000)
+            / U256::from(self.shares_total_supply))
+        .as_u128()
+    }
+
+    /// Same as `get_share_price`, returning an error instead of panicking.
+    pub fn try_get_share_price(&self, token_id: &AccountId) -> Result<u128, ErrorCode> {
+        if !self.token_account_ids.contains(token_id) {
+            return Err(ErrorCode::E63MissingToken);
+        }
+        if self.shares_total_supply == 0 {
+            return Err(ErrorCode::E87EmptyPool);
+        }
+        Ok(self.get_share_price(token_id))
+    }
+
+    /// Returns price of each token in the first token, as amount of the first token per unit, scaled by `precision`.
+    /// None if any of the reserves is empty.
+    pub fn spot_prices(&self, precision: U256) -> Option<Vec<U256>> {
+        if self.amounts.contains(&0) {
+            return None;
+        }
+        Some(
+            self.amounts
+                .iter()
+                .map(|amount| U256::from(self.amounts[0]) * precision / U256::from(*amount))
+                .collect(),
+        )
+    }
+
     /// Returns total number of shares in this pool.
     pub fn share_total_balance(&self) -> Balance {
         self.shares_total_supply
@@ -107,6 +218,66 @@ impl SimplePool {
         &self.token_account_ids
     }
 
+    /// Replaces given token with a new token account, returns the pool's reserve of it.
+    pub fn migrate_token(&mut self, old_token_id: &AccountId, new_token_id: &AccountId) -> Balance {
+        assert!(!self.token_account_ids.contains(new_token_id), "{}", ERR80_ILLEGAL_TOKEN_MIGRATION);
+        let index = self.token_index(old_token_id);
+        self.token_account_ids[index] = new_token_id.clone();
+        self.amounts[index]
+    }
+
+    /// Returns number of shares that adding given amounts would mint, and amounts kept in the pool.
+    /// The rest of the amounts is refunded.
+    fn calc_add_liquidity(&self, amounts: &[Balance]) -> Result<(Balance, Vec<Balance>), ErrorCode> {
+        if amounts.len() != self.token_account_ids.len() {
+            return Err(ErrorCode::E64TokensCountIllegal);
+        }
+        if self.shares_total_supply == 0 {
+            return Ok((INIT_SHARES_SUPPLY, amounts.to_vec()));
+        }
+        let mut fair_supply = U256::max_value();
+        for i in 0..self.token_account_ids.len() {
+            if amounts[i] == 0 {
+                return Err(ErrorCode::E31ZeroAmount);
+            }
+            fair_supply = min(
+                fair_supply,
+                U256::from(amounts[i]) * U256::from(self.shares_total_supply) / self.amounts[i],
+            );
+        }
+        let mut kept_amounts = vec![];
+        for i in 0..self.token_account_ids.len() {
+            let amount = (U256::from(self.amounts[i]) * fair_supply
+                / U256::from(self.shares_total_supply))
+            .as_u128();
+            if amount == 0 {
+                return Err(ErrorCode::E31ZeroAmount);
+            }
+            kept_amounts.push(amount);
+        }
+        Ok((fair_supply.as_u128(), kept_amounts))
+    }
+
+    /// Returns number of shares `add_liquidity` would mint for given amounts, and amounts it would refund.
+    pub fn predict_add_liquidity(&self, amounts: &[Balance]) -> (Balance, Vec<Balance>) {
+        self.try_predict_add_liquidity(amounts)
+            .unwrap_or_else(|err| env::panic(err.message().as_bytes()))
+    }
+
+    /// Same as `predict_add_liquidity`, returning an error instead of panicking.
+    pub fn try_predict_add_liquidity(&self, amounts: &[Balance]) -> Result<(Balance, Vec<Balance>), ErrorCode> {
+        let (shares, kept_amounts) = self.calc_add_liquidity(amounts)?;
+        if shares == 0 {
+            return Err(ErrorCode::E32ZeroShares);
+        }
+        let refunds = amounts
+            .iter()
+            .zip(kept_amounts.iter())
+            .map(|(amount, kept_amount)| amount - kept_amount)
+            .collect();
+        Ok((shares, refunds))
+    }
+
     /// Adds the amounts of tokens to liquidity pool and returns number of shares that this user receives.
     /// Updates amount to amount kept in the pool.
     pub fn add_liquidity(&mut self, sender_id: &AccountId, amounts: &mut Vec<Balance>) -> Balance {
@@ -115,44 +286,15 @@ impl SimplePool {
             self.token_account_ids.len(),
             "ERR_WRONG_TOKEN_COUNT"
         );
-        let shares = if self.shares_total_supply > 0 {
-            let mut fair_supply = U256::max_value();
-            for i in 0..self.token_account_ids.len() {
-                assert!(amounts[i] > 0, "{}", ERR31_ZERO_AMOUNT);
-                fair_supply = min(
-                    fair_supply,
-                    U256::from(amounts[i]) * U256::from(self.shares_total_supply) / self.amounts[i],
-                );
-            }
-            for i in 0..self.token_account_ids.len() {
-                let amount = (U256::from(self.amounts[i]) * fair_supply
-                    / U256::from(self.shares_total_supply))
-                .as_u128();
-                assert!(amount > 0, "{}", ERR31_ZERO_AMOUNT);
-                self.amounts[i] += amount;
-                amounts[i] = amount;
-            }
-            fair_supply.as_u128()
-        } else {
-            for i in 0..self.token_account_ids.len() {
-                self.amounts[i] += amounts[i];
-            }
-            INIT_SHARES_SUPPLY
-        };
+        let (shares, kept_amounts) = self
+            .calc_add_liquidity(amounts)
+            .unwrap_or_else(|err| env::panic(err.message().as_bytes()));
+        for (amount, kept_amount) in self.amounts.iter_mut().zip(kept_amounts.iter()) {
+            *amount += kept_amount;
+        }
+        *amounts = kept_amounts;
         self.mint_shares(&sender_id, shares);
         assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
-        env::log(
-            format!(
-                "Liquidity added {:?}, minted {} shares",
-                amounts
-                    .iter()
-                    .zip(self.token_account_ids.iter())
-                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
-                    .collect::<Vec<String>>(),
-                shares
-            )
-            .as_bytes(),
-        );
         shares
     }
 
@@ -190,23 +332,12 @@ impl SimplePool {
         }
         if prev_shares_amount == shares {
             // [AUDIT_13] Never unregister a LP when he removed all his liquidity.
+            // The entry can be freed with `mft_unregister`.
             self.shares.insert(&sender_id, &0);
         } else {
             self.shares
                 .insert(&sender_id, &(prev_shares_amount - shares));
         }
-        env::log(
-            format!(
-                "{} shares of liquidity removed: receive back {:?}",
-                shares,
-                result
-                    .iter()
-                    .zip(self.token_account_ids.iter())
-                    .map(|(amount, token_id)| format!("{} {}", amount, token_id))
-                    .collect::<Vec<String>>(),
-            )
-            .as_bytes(),
-        );
         self.shares_total_supply -= shares;
         result
     }
@@ -255,6 +386,48 @@ impl SimplePool {
         )
     }
 
+    /// Same as `get_return`, returning an error instead of panicking.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Result<Balance, ErrorCode> {
+        let index = |token_id: &AccountId| {
+            self.token_account_ids
+                .iter()
+                .position(|id| id == token_id)
+                .ok_or(ErrorCode::E63MissingToken)
+        };
+        let (in_idx, out_idx) = (index(token_in)?, index(token_out)?);
+        if in_idx == out_idx {
+            return Err(ErrorCode::E71SwapDupTokens);
+        }
+        if amount_in == 0 {
+            return Err(ErrorCode::E31ZeroAmount);
+        }
+        if self.amounts[in_idx] == 0 || self.amounts[out_idx] == 0 {
+            return Err(ErrorCode::E87EmptyPool);
+        }
+        Ok(self.internal_get_return(in_idx, amount_in, out_idx))
+    }
+
+    /// Returns the part of `amount_in` of `token_in` to swap into the other token, so that what is left
+    /// and what is received are in the pool's ratio after the swap, and can be added as liquidity.
+    /// Solves `s^2 (1 - f) + s R (2 - f) - A R = 0` for `s`, where `A` is `amount_in`, `R` the reserve
+    /// of `token_in` and `f` the pool fee.
+    pub fn zap_swap_amount(&self, token_in: &AccountId, amount_in: Balance) -> Balance {
+        let reserve_in = U256::from(self.amounts[self.token_index(token_in)]);
+        assert!(reserve_in > U256::zero(), "{}", ERR87_EMPTY_POOL);
+        let fee_divisor = U256::from(FEE_DIVISOR);
+        let fee = U256::from(self.total_fee);
+        let b = reserve_in * (fee_divisor * 2 - fee);
+        let root = integer_sqrt(
+            b * b + U256::from(4) * fee_divisor * (fee_divisor - fee) * U256::from(amount_in) * reserve_in,
+        );
+        ((root - b) / ((fee_divisor - fee) * 2)).as_u128()
+    }
+
     /// Returns given pool's total fee.
     pub fn get_fee(&self) -> u32 {
         self.total_fee
@@ -265,6 +438,33 @@ impl SimplePool {
         self.volumes.clone()
     }
 
+    /// Returns the error `swap` would fail with for given arguments, if any.
+    pub fn check_swap(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+    ) -> Result<(), &'static str> {
+        if token_in == token_out {
+            return Err("ERR_SAME_TOKEN_SWAP");
+        }
+        let index = |token_id: &AccountId| {
+            self.token_account_ids
+                .iter()
+                .position(|id| id == token_id)
+                .ok_or("ERR_MISSING_TOKEN")
+        };
+        let (in_idx, out_idx) = (index(token_in)?, index(token_out)?);
+        if self.amounts[in_idx] == 0 || self.amounts[out_idx] == 0 || amount_in == 0 {
+            return Err("ERR_INVALID");
+        }
+        if self.internal_get_return(in_idx, amount_in, out_idx) < min_amount_out {
+            return Err("ERR_MIN_AMOUNT");
+        }
+        Ok(())
+    }
+
     /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
     /// Assuming that `token_amount_in` was already received from `sender_id`.
     pub fn swap(
@@ -275,18 +475,24 @@ impl SimplePool {
         min_amount_out: Balance,
         admin_fee: &AdminFees,
     ) -> Balance {
+        self.swap_detailed(token_in, amount_in, token_out, min_amount_out, admin_fee)
+            .amount_out
+    }
+
+    /// Same as `swap`, also returning the fee kept by liquidity providers, charged in token_in.
+    pub fn swap_detailed(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        admin_fee: &AdminFees,
+    ) -> SwapOutcome {
         assert_ne!(token_in, token_out, "ERR_SAME_TOKEN_SWAP");
         let in_idx = self.token_index(token_in);
         let out_idx = self.token_index(token_out);
         let amount_out = self.internal_get_return(in_idx, amount_in, out_idx);
         assert!(amount_out >= min_amount_out, "ERR_MIN_AMOUNT");
-        env::log(
-            format!(
-                "Swapped {} {} for {} {}",
-                amount_in, token_in, amount_out, token_out
-            )
-            .as_bytes(),
-        );
 
         let prev_invariant =
             integer_sqrt(U256::from(self.amounts[in_idx]) * U256::from(self.amounts[out_idx]));
@@ -302,10 +508,12 @@ impl SimplePool {
         assert!(new_invariant >= prev_invariant, "ERR_INVARIANT");
         let numerator = (new_invariant - prev_invariant) * U256::from(self.shares_total_supply);
 
+        let mut admin_fee_bps = 0;
         // Allocate exchange fee as fraction of total fee by issuing LP shares proportionally.
         if admin_fee.exchange_fee > 0 && numerator > U256::zero() {
             let denominator = new_invariant * FEE_DIVISOR / admin_fee.exchange_fee;
             self.mint_shares(&admin_fee.exchange_id, (numerator / denominator).as_u128());
+            admin_fee_bps += admin_fee.exchange_fee;
         }
 
         // If there is referral provided and the account already registered LP, allocate it % of LP rewards.
@@ -316,6 +524,7 @@ impl SimplePool {
             {
                 let denominator = new_invariant * FEE_DIVISOR / admin_fee.referral_fee;
                 self.mint_shares(referral_id, (numerator / denominator).as_u128());
+                admin_fee_bps += admin_fee.referral_fee;
             }
         }
 
@@ -324,7 +533,15 @@ impl SimplePool {
         self.volumes[in_idx].input.0 += amount_in;
         self.volumes[in_idx].output.0 += amount_out;
 
-        amount_out
+        let fee = U256::from(amount_in) * U256::from(self.total_fee) / U256::from(FEE_DIVISOR);
+        let lp_fee = (fee * U256::from(FEE_DIVISOR - admin_fee_bps) / U256::from(FEE_DIVISOR)).as_u128();
+        SwapOutcome {
+            amount_out,
+            fee_token: in_idx,
+            total_fee: fee.as_u128(),
+            admin_fee: fee.as_u128() - lp_fee,
+            lp_fee,
+        }
     }
 }
 
@@ -442,4 +659,36 @@ mod tests {
         let mut amounts = vec![145782, 1];
         let _ = pool.add_liquidity(&accounts(2).to_string(), &mut amounts);
     }
+
+    #[test]
+    fn test_predict_liquidity() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        let mut pool = SimplePool::new(0, vec![accounts(1), accounts(2)], 30, 0, 0);
+        assert_eq!(pool.predict_remove_liquidity(to_yocto("1")), vec![0, 0]);
+        let mut amounts = vec![to_yocto("5"), to_yocto("10")];
+        pool.add_liquidity(accounts(0).as_ref(), &mut amounts);
+        // 2 tokens worth 5 of the first one each, for one whole share.
+        assert_eq!(pool.get_share_price(accounts(1).as_ref()), 10 * 100000000);
+        assert_eq!(pool.get_share_price(accounts(2).as_ref()), 20 * 100000000);
+
+        let (shares, refunds) = pool.predict_add_liquidity(&[to_yocto("1"), to_yocto("3")]);
+        assert_eq!(refunds, vec![0, to_yocto("1")]);
+        let mut amounts = vec![to_yocto("1"), to_yocto("3")];
+        assert_eq!(pool.add_liquidity(accounts(1).as_ref(), &mut amounts), shares);
+        assert_eq!(amounts, vec![to_yocto("1"), to_yocto("2")]);
+
+        let shares = pool.predict_remove_liquidity_by_tokens(&[to_yocto("1"), 0]);
+        assert_eq!(pool.predict_remove_liquidity(shares)[0], to_yocto("1"));
+        assert_eq!(
+            pool.remove_liquidity(accounts(0).as_ref(), shares, vec![to_yocto("1"), 1]),
+            pool.predict_remove_liquidity(shares)
+        );
+        assert_eq!(
+            pool.try_predict_remove_liquidity_by_tokens(&[to_yocto("7"), 0]),
+            Err(ErrorCode::E34InsufficientLpShares)
+        );
+        assert_eq!(pool.try_predict_add_liquidity(&[1, 0]), Err(ErrorCode::E31ZeroAmount));
+    }
 }
@@ -0,0 +1,149 @@
+//! Dry run of `execute_actions` for a given account.
+//!
+//! Runs the same checks as the real call, in the same order and through the same helpers,
+//! and executes the swaps on in-memory copies of the pools, with balance changes kept aside
+//! from the account, so nothing is written to storage. The first failing check is reported instead of panicking.
+
+use std::collections::{HashMap, HashSet};
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::quote::SwapDetail;
+use crate::*;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct SimulationResult {
+    /// Steps that succeed, with amounts and fees charged.
+    pub steps: Vec<SwapDetail>,
+    /// Balances of the tokens used in actions after the succeeding steps.
+    /// Nothing is applied by the real call if any step fails.
+    pub balances: HashMap<AccountId, U128>,
+    /// Index of the action that fails, None if the whole batch succeeds
+    /// or it fails on account or storage checks outside of any action.
+    pub failed_step: Option<u32>,
+    /// Error the real call would fail with.
+    pub error: Option<String>,
+}
+
+impl Contract {
+    fn internal_simulate_actions(
+        &self,
+        account_id: &AccountId,
+        actions: &[Action],
+        referral_id: &Option<AccountId>,
+        result: &mut SimulationResult,
+    ) -> Result<(), (Option<u32>, &'static str)> {
+        if self.state != RunningState::Running {
+            return Err((None, ERR51_CONTRACT_PAUSED));
+        }
+        let account = self
+            .internal_get_account(account_id)
+            .ok_or((None, ERR10_ACC_NOT_REGISTERED))?;
+        self.check_action_tokens(&account, actions)
+            .map_err(|(index, err)| (Some(index as u32), err))?;
+        for action in actions.iter() {
+            for token in action.tokens() {
+                let balance = account.get_balance(&token).unwrap_or(0);
+                result.balances.insert(token, U128(balance));
+            }
+        }
+        let fees = AdminFees {
+            exchange_fee: self.exchange_fee,
+            exchange_id: env::current_account_id(),
+            referral_fee: self.referral_fee,
+            referral_id: referral_id.clone(),
+        };
+        let mut pools = HashMap::new();
+        let mut prev_amount = None;
+        // Tokens the account holds, the ones received in earlier steps get registered by the deposit.
+        let mut registered: HashSet<AccountId> = result
+            .balances
+            .keys()
+            .filter(|token| account.get_balance(token).is_some())
+            .cloned()
+            .collect();
+        for (index, action) in actions.iter().enumerate() {
+            let Action::Swap(swap_action) = action;
+            let step = Some(index as u32);
+            let amount_in = swap_action
+                .amount_in
+                .map(|amount| amount.0)
+                .or(prev_amount)
+                .ok_or((step, ERR41_WRONG_ACTION_RESULT))?;
+            if !registered.contains(&swap_action.token_in) {
+                return Err((step, ERR21_TOKEN_NOT_REG));
+            }
+            let balance_in = result.balances[&swap_action.token_in].0;
+            if balance_in < amount_in {
+                return Err((step, ERR22_NOT_ENOUGH_TOKENS));
+            }
+            self.check_pool_swap(swap_action.pool_id, &swap_action.token_out)
+                .map_err(|err| (step, err))?;
+            let pool = pools
+                .entry(swap_action.pool_id)
+                .or_insert_with(|| self.pools.get(swap_action.pool_id).unwrap());
+            pool.check_swap(
+                &swap_action.token_in,
+                amount_in,
+                &swap_action.token_out,
+                swap_action.min_amount_out.0,
+                &fees,
+            )
+            .map_err(|err| (step, err))?;
+            let detail = self.internal_quote_swap(
+                &mut pools,
+                swap_action.pool_id,
+                &swap_action.token_in,
+                amount_in,
+                &swap_action.token_out,
+                referral_id,
+            );
+            result.balances.insert(swap_action.token_in.clone(), U128(balance_in - amount_in));
+            result.balances.get_mut(&swap_action.token_out).unwrap().0 += detail.amount_out.0;
+            registered.insert(swap_action.token_out.clone());
+            prev_amount = Some(detail.amount_out.0);
+            result.steps.push(detail);
+        }
+        // Tokens received but not registered yet are added to the account when it is saved.
+        let new_tokens = registered
+            .iter()
+            .filter(|token| account.get_balance(token).is_none())
+            .count();
+        if account.storage_usage_with_new_tokens(new_tokens as u64) > account.near_amount {
+            return Err((None, ERR11_INSUFFICIENT_STORAGE));
+        }
+        Ok(())
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Simulates `execute_actions` called by given account without attached deposit,
+    /// so tokens neither registered in the account nor whitelisted are rejected.
+    /// Returns the outcome of each step, or the error the call would fail with.
+    pub fn simulate_actions(
+        &self,
+        account_id: ValidAccountId,
+        actions: Vec<Action>,
+        referral_id: Option<ValidAccountId>,
+    ) -> SimulationResult {
+        let mut result = SimulationResult {
+            steps: vec![],
+            balances: HashMap::new(),
+            failed_step: None,
+            error: None,
+        };
+        let referral_id = referral_id.map(|r| r.into());
+        if let Err((failed_step, error)) =
+            self.internal_simulate_actions(account_id.as_ref(), &actions, &referral_id, &mut result)
+        {
+            result.failed_step = failed_step;
+            result.error = Some(error.to_string());
+        }
+        result
+    }
+}
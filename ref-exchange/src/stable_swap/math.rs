@@ -353,6 +353,102 @@ impl StableSwap {
 
     }
 
+    /// Compute new amount of token 'y' that keeps the invariant at given `d`,
+    /// with all other tokens at their amount in `c_amounts`.
+    pub fn compute_y_d(
+        &self,
+        d: U256, // target invariant,
+        c_amounts: &[Balance], // in-pool tokens amount in comparable precision,
+        index_y: usize, // y token's index
+    ) -> Option<U256> {
+        let n_coins = c_amounts.len() as u128;
+        let amp_factor = self.compute_amp_factor()?;
+        let ann = amp_factor.checked_mul(n_coins.checked_pow(n_coins as u32)?)?;
+        let mut s_: u128 = 0;
+        let mut c = d;
+        for (idx, c_amount) in c_amounts.iter().enumerate() {
+            if idx != index_y {
+                s_ = s_.checked_add(*c_amount)?;
+                c = c.checked_mul(d)?
+                    .checked_div((*c_amount).into())?;
+            }
+        }
+        c = c
+            .checked_mul(d)?
+            .checked_div(ann.checked_mul(n_coins.checked_pow(n_coins as u32)?)?.into())?;
+
+        let b = d.checked_div(ann.into())?.checked_add(s_.into())?; // d will be subtracted later
+
+        // Solve for y by approximating: y**2 + b*y = c
+        let mut y_prev: U256;
+        let mut y = d;
+        for _ in 0..256 {
+            y_prev = y;
+            let y_numerator = y.checked_pow(2.into())?.checked_add(c)?;
+            let y_denominator = y.checked_mul(2.into())?.checked_add(b)?.checked_sub(d)?;
+            y = y_numerator.checked_div(y_denominator)?;
+            if y > y_prev {
+                if y.checked_sub(y_prev)? <= 1.into() {
+                    break;
+                }
+            } else if y_prev.checked_sub(y)? <= 1.into() {
+                break;
+            }
+        }
+        Some(y)
+    }
+
+    /// given lp amount to burn and the only token user want get,
+    /// return <token_out_c_amount, lp_fees_part>
+    /// all amounts are in c_amount (comparable amount)
+    pub fn compute_withdraw_one_coin(
+        &self,
+        burn_shares: Balance, // shares to burn,
+        index_out: usize, // token_out index in token vector,
+        old_c_amounts: &[Balance], // in-pool tokens comparable amounts vector,
+        pool_token_supply: Balance, // total share supply
+        fees: &Fees,
+    ) -> Option<(Balance, Balance)> {
+        let n_coins = old_c_amounts.len();
+        // Initial invariant, D0
+        let d_0 = self.compute_d(&old_c_amounts.to_vec())?;
+        // Invariant reduced by the burnt shares, D1
+        let d_1 = d_0.checked_sub(
+            d_0.checked_mul(burn_shares.into())?
+                .checked_div(pool_token_supply.into())?,
+        )?;
+        let new_y = self.compute_y_d(d_1, old_c_amounts, index_out)?.as_u128();
+
+        // Charge fee on the difference with ideal token portions from D1.
+        let mut reduced_balances = old_c_amounts.to_vec();
+        for (i, reduced_balance) in reduced_balances.iter_mut().enumerate() {
+            let ideal_balance = d_1
+                .checked_mul(old_c_amounts[i].into())?
+                .checked_div(d_0)?
+                .as_u128();
+            let difference = if i == index_out {
+                ideal_balance.checked_sub(new_y)?
+            } else {
+                old_c_amounts[i].checked_sub(ideal_balance)?
+            };
+            let fee = fees.normalized_trade_fee(n_coins as u32, difference);
+            *reduced_balance = reduced_balance.checked_sub(fee)?;
+        }
+
+        // Round down by one in favor of the pool.
+        let amount_out = reduced_balances[index_out]
+            .checked_sub(self.compute_y_d(d_1, &reduced_balances, index_out)?.as_u128())?
+            .checked_sub(1)?;
+        // Shares burnt for the fee, in proportion of the fee to the amount out without fee.
+        let amount_out_no_fee = old_c_amounts[index_out].checked_sub(new_y)?;
+        let fee_part = U256::from(burn_shares)
+            .checked_mul(amount_out_no_fee.checked_sub(amount_out)?.into())?
+            .checked_div(amount_out_no_fee.into())?
+            .as_u128();
+
+        Some((amount_out, fee_part))
+    }
+
     /// Compute SwapResult after an exchange
     /// all tokens in and out with comparable precision
     pub fn swap_to(
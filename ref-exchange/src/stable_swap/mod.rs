@@ -8,7 +8,7 @@ use crate::errors::*;
 use crate::stable_swap::math::{
     Fees, StableSwap, SwapResult, MAX_AMP, MAX_AMP_CHANGE, MIN_AMP, MIN_RAMP_DURATION,
 };
-use crate::utils::{add_to_collection, SwapVolume, FEE_DIVISOR, U256};
+use crate::utils::{add_to_collection, SwapOutcome, SwapVolume, FEE_DIVISOR, U256};
 use crate::StorageKey;
 
 mod math;
@@ -17,6 +17,8 @@ pub const MIN_DECIMAL: u8 = 1;
 pub const MAX_DECIMAL: u8 = 18;
 pub const TARGET_DECIMAL: u8 = 18;
 pub const MIN_RESERVE: u128 = 1_000_000_000_000_000_000;
+/// Spot prices are quoted by swapping this fraction of the input reserve.
+const PRICE_PROBE_DIVISOR: u128 = 1_000_000;
 
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct StableSwapPool {
@@ -99,6 +101,22 @@ impl StableSwapPool {
         c_amounts
     }
 
+    /// Same as `amounts_to_c_amounts`, also checking the number of amounts.
+    fn try_amounts_to_c_amounts(&self, amounts: &[u128]) -> Result<Vec<u128>, ErrorCode> {
+        if amounts.len() != self.token_account_ids.len() {
+            return Err(ErrorCode::E64TokensCountIllegal);
+        }
+        amounts
+            .iter()
+            .zip(self.token_decimals.iter())
+            .map(|(amount, decimal)| {
+                amount
+                    .checked_mul(10_u128.pow((TARGET_DECIMAL - decimal) as u32))
+                    .ok_or(ErrorCode::E67LpshareCalcErr)
+            })
+            .collect()
+    }
+
     fn amount_to_c_amount(&self, amount: u128, index: usize) -> u128 {
         let value = self.token_decimals.get(index).unwrap();
         let factor = 10_u128
@@ -149,6 +167,33 @@ impl StableSwapPool {
             .expect(ERR63_MISSING_TOKEN)
     }
 
+    /// Returns price of each token in the first token, as amount of the first token per unit, scaled by `precision`.
+    /// Quoted by a fee-less swap of a small part of the reserve, None if the pool is empty.
+    pub fn spot_prices(&self, precision: U256) -> Option<Vec<U256>> {
+        if self.c_amounts.contains(&0) {
+            return None;
+        }
+        let invariant = self.get_invariant();
+        let factor = |index: usize| {
+            U256::from(10_u128.pow((TARGET_DECIMAL - self.token_decimals[index]) as u32))
+        };
+        (0..self.c_amounts.len())
+            .map(|index| {
+                if index == 0 {
+                    return Some(precision);
+                }
+                let c_amount_in = std::cmp::max(self.c_amounts[index] / PRICE_PROBE_DIVISOR, 1);
+                let c_amount_out = invariant
+                    .swap_to(index, c_amount_in, 0, &self.c_amounts, &Fees::zero())?
+                    .amount_swapped;
+                Some(
+                    U256::from(c_amount_out) * precision / U256::from(c_amount_in) * factor(index)
+                        / factor(0),
+                )
+            })
+            .collect()
+    }
+
     /// Returns given pool's total fee.
     pub fn get_fee(&self) -> u32 {
         self.total_fee
@@ -172,6 +217,14 @@ impl StableSwapPool {
             .as_u128()
     }
 
+    /// Same as `get_share_price`, returning an error instead of panicking.
+    pub fn try_get_share_price(&self) -> Result<u128, ErrorCode> {
+        if self.shares_total_supply == 0 {
+            return Err(ErrorCode::E87EmptyPool);
+        }
+        Ok(self.get_share_price())
+    }
+
     /// caculate mint share and related fee for adding liquidity
     /// return (share, fee_part)
     fn calc_add_liquidity(
@@ -224,6 +277,34 @@ impl StableSwapPool {
         new_shares
     }
 
+    /// Same as `predict_add_stable_liquidity`, returning an error instead of panicking.
+    pub fn try_predict_add_stable_liquidity(
+        &self,
+        amounts: &[Balance],
+        fees: &AdminFees,
+    ) -> Result<Balance, ErrorCode> {
+        let c_amounts = self.try_amounts_to_c_amounts(amounts)?;
+        let invariant = self.get_invariant();
+        if self.shares_total_supply == 0 {
+            if c_amounts.contains(&0) {
+                return Err(ErrorCode::E65InitTokenBalance);
+            }
+            return invariant
+                .compute_d(&c_amounts)
+                .map(|d| d.as_u128())
+                .ok_or(ErrorCode::E66InvariantCalcErr);
+        }
+        invariant
+            .compute_lp_amount_for_deposit(
+                &c_amounts,
+                &self.c_amounts,
+                self.shares_total_supply,
+                &Fees::new(self.total_fee, fees),
+            )
+            .map(|(shares, _)| shares)
+            .ok_or(ErrorCode::E67LpshareCalcErr)
+    }
+
     /// Add liquidity into the pool.
     /// Allows to add liquidity of a subset of tokens,
     /// by set other tokens balance into 0.
@@ -247,13 +328,6 @@ impl StableSwapPool {
         }
 
         self.mint_shares(sender_id, new_shares);
-        env::log(
-            format!(
-                "Mint {} shares for {}, fee is {} shares",
-                new_shares, sender_id, fee_part,
-            )
-            .as_bytes(),
-        );
 
         if fee_part > 0 {
             // referral fee
@@ -261,17 +335,11 @@ impl StableSwapPool {
                 if self.shares.get(referral).is_some() {
                     let referral_share = fee_part * fees.referral_fee as u128 / FEE_DIVISOR as u128;
                     self.mint_shares(referral, referral_share);
-                    env::log(
-                        format!("Referral {} got {} shares", referral, referral_share).as_bytes(),
-                    );
                 }
             }
             // exchange fee
             let exchange_share = fee_part * fees.exchange_fee as u128 / FEE_DIVISOR as u128;
             self.mint_shares(&fees.exchange_id, exchange_share);
-            env::log(
-                format!("Admin {} got {} shares", &fees.exchange_id, exchange_share).as_bytes(),
-            );
         }
         new_shares
     }
@@ -294,6 +362,14 @@ impl StableSwapPool {
         result
     }
 
+    /// Same as `predict_remove_liquidity`, returning an error instead of panicking.
+    pub fn try_predict_remove_liquidity(&self, shares: Balance) -> Result<Vec<Balance>, ErrorCode> {
+        if shares > self.shares_total_supply || self.shares_total_supply == 0 {
+            return Err(ErrorCode::E34InsufficientLpShares);
+        }
+        Ok(self.predict_remove_liquidity(shares))
+    }
+
     /// balanced removal of liquidity would be free of charge.
     pub fn remove_liquidity_by_shares(
         &mut self,
@@ -325,14 +401,6 @@ impl StableSwapPool {
         }
 
         self.burn_shares(&sender_id, prev_shares_amount, shares);
-        
-        env::log(
-            format!(
-                "LP {} remove {} shares to gain tokens {:?}",
-                sender_id, shares, result
-            )
-            .as_bytes(),
-        );
 
         result
     }
@@ -363,6 +431,29 @@ impl StableSwapPool {
         burn_shares
     }
 
+    /// Same as `predict_remove_liquidity_by_tokens`, returning an error instead of panicking.
+    pub fn try_predict_remove_liquidity_by_tokens(
+        &self,
+        amounts: &[Balance],
+        fees: &AdminFees,
+    ) -> Result<Balance, ErrorCode> {
+        let c_amounts = self.try_amounts_to_c_amounts(amounts)?;
+        for (c_amount, c_amount_out) in self.c_amounts.iter().zip(c_amounts.iter()) {
+            if c_amount.checked_sub(*c_amount_out).unwrap_or(0) < MIN_RESERVE {
+                return Err(ErrorCode::E69MinReserve);
+            }
+        }
+        self.get_invariant()
+            .compute_lp_amount_for_withdraw(
+                &c_amounts,
+                &self.c_amounts,
+                self.shares_total_supply,
+                &Fees::new(self.total_fee, fees),
+            )
+            .map(|(burn_shares, _)| burn_shares)
+            .ok_or(ErrorCode::E67LpshareCalcErr)
+    }
+
     /// Remove liquidity from the pool by fixed tokens-out,
     /// allows to remove liquidity of a subset of tokens, by providing 0 in `amounts`.
     /// Fee will be charged according to diff between ideal token portions.
@@ -407,13 +498,80 @@ impl StableSwapPool {
             self.assert_min_reserve(self.c_amounts[i]);
         }
         self.burn_shares(&sender_id, prev_shares_amount, burn_shares);
-        env::log(
-            format!(
-                "LP {} removed {} shares by given tokens, and fee is {} shares",
-                sender_id, burn_shares, fee_part
+
+        if fee_part > 0 {
+            // referral fee
+            if let Some(referral) = &fees.referral_id {
+                if self.shares.get(referral).is_some() {
+                    let referral_share = fee_part * fees.referral_fee as u128 / FEE_DIVISOR as u128;
+                    self.mint_shares(referral, referral_share);
+                }
+            }
+            // exchange fee
+            let exchange_share = fee_part * fees.exchange_fee as u128 / FEE_DIVISOR as u128;
+            self.mint_shares(&fees.exchange_id, exchange_share);
+        }
+
+        burn_shares
+    }
+
+    /// Returns amount of given token and share fee part from burning `shares` for that token only.
+    fn calc_remove_liquidity_one_coin(
+        &self,
+        shares: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> (Balance, Balance) {
+        assert!(shares > 0, "{}", ERR32_ZERO_SHARES);
+        assert!(shares <= self.shares_total_supply, "{}", ERR34_INSUFFICIENT_LP_SHARES);
+        let index = self.token_index(token_out);
+        let (c_amount_out, fee_part) = self
+            .get_invariant()
+            .compute_withdraw_one_coin(
+                shares,
+                index,
+                &self.c_amounts,
+                self.shares_total_supply,
+                &Fees::new(self.total_fee, fees),
             )
-            .as_bytes(),
+            .expect(ERR67_LPSHARE_CALC_ERR);
+        (self.c_amount_to_amount(c_amount_out, index), fee_part)
+    }
+
+    pub fn predict_remove_liquidity_one_coin(
+        &self,
+        shares: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Balance {
+        self.calc_remove_liquidity_one_coin(shares, token_out, fees).0
+    }
+
+    /// Remove liquidity from the pool by burning `shares`, all of it withdrawn in `token_out`.
+    /// Fee will be charged according to diff between ideal token portions.
+    pub fn remove_liquidity_one_coin(
+        &mut self,
+        sender_id: &AccountId,
+        shares: Balance,
+        token_out: &AccountId,
+        min_amount: Balance,
+        fees: &AdminFees,
+    ) -> Balance {
+        let prev_shares_amount = self.shares.get(sender_id).expect(ERR13_LP_NOT_REGISTERED);
+        assert!(
+            prev_shares_amount >= shares,
+            "{}",
+            ERR34_INSUFFICIENT_LP_SHARES
         );
+        let (amount, fee_part) = self.calc_remove_liquidity_one_coin(shares, token_out, fees);
+        assert!(amount >= min_amount, "{}", ERR68_SLIPPAGE);
+
+        let index = self.token_index(token_out);
+        self.c_amounts[index] = self.c_amounts[index]
+            .checked_sub(self.amount_to_c_amount(amount, index))
+            .unwrap();
+        self.assert_min_reserve(self.c_amounts[index]);
+        self.burn_shares(sender_id, prev_shares_amount, shares);
 
         if fee_part > 0 {
             // referral fee
@@ -421,20 +579,14 @@ impl StableSwapPool {
                 if self.shares.get(referral).is_some() {
                     let referral_share = fee_part * fees.referral_fee as u128 / FEE_DIVISOR as u128;
                     self.mint_shares(referral, referral_share);
-                    env::log(
-                        format!("Referral {} got {} shares", referral, referral_share).as_bytes(),
-                    );
                 }
             }
             // exchange fee
             let exchange_share = fee_part * fees.exchange_fee as u128 / FEE_DIVISOR as u128;
             self.mint_shares(&fees.exchange_id, exchange_share);
-            env::log(
-                format!("Admin {} got {} shares", &fees.exchange_id, exchange_share).as_bytes(),
-            );
         }
 
-        burn_shares
+        amount
     }
 
     /// Returns number of tokens in outcome, given amount.
@@ -481,6 +633,78 @@ impl StableSwapPool {
         self.c_amount_to_amount(c_amount_out, self.token_index(token_out))
     }
 
+    /// Same as `get_return`, returning an error instead of panicking.
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        fees: &AdminFees,
+    ) -> Result<Balance, ErrorCode> {
+        if token_in == token_out {
+            return Err(ErrorCode::E71SwapDupTokens);
+        }
+        let index = |token_id: &AccountId| {
+            self.token_account_ids
+                .iter()
+                .position(|id| id == token_id)
+                .ok_or(ErrorCode::E63MissingToken)
+        };
+        let (in_idx, out_idx) = (index(token_in)?, index(token_out)?);
+        let c_amount_in = amount_in
+            .checked_mul(10_u128.pow((TARGET_DECIMAL - self.token_decimals[in_idx]) as u32))
+            .ok_or(ErrorCode::E70SwapOutCalcErr)?;
+        let result = self
+            .get_invariant()
+            .swap_to(
+                in_idx,
+                c_amount_in,
+                out_idx,
+                &self.c_amounts,
+                &Fees::new(self.total_fee, fees),
+            )
+            .ok_or(ErrorCode::E70SwapOutCalcErr)?;
+        Ok(self.c_amount_to_amount(result.amount_swapped, out_idx))
+    }
+
+    /// Returns the error `swap` would fail with for given arguments, if any.
+    pub fn check_swap(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        fees: &AdminFees,
+    ) -> Result<(), &'static str> {
+        if token_in == token_out {
+            return Err(ERR71_SWAP_DUP_TOKENS);
+        }
+        let index = |token_id: &AccountId| {
+            self.token_account_ids
+                .iter()
+                .position(|id| id == token_id)
+                .ok_or(ERR63_MISSING_TOKEN)
+        };
+        let (in_idx, out_idx) = (index(token_in)?, index(token_out)?);
+        let result = self
+            .get_invariant()
+            .swap_to(
+                in_idx,
+                self.amount_to_c_amount(amount_in, in_idx),
+                out_idx,
+                &self.c_amounts,
+                &Fees::new(self.total_fee, fees),
+            )
+            .ok_or(ERR70_SWAP_OUT_CALC_ERR)?;
+        if self.c_amount_to_amount(result.amount_swapped, out_idx) < min_amount_out {
+            return Err(ERR68_SLIPPAGE);
+        }
+        if result.new_destination_amount < MIN_RESERVE {
+            return Err(ERR69_MIN_RESERVE);
+        }
+        Ok(())
+    }
+
     /// Swap `token_amount_in` of `token_in` token into `token_out` and return how much was received.
     /// Assuming that `token_amount_in` was already received from `sender_id`.
     pub fn swap(
@@ -491,6 +715,19 @@ impl StableSwapPool {
         min_amount_out: Balance,
         fees: &AdminFees,
     ) -> Balance {
+        self.swap_detailed(token_in, amount_in, token_out, min_amount_out, fees)
+            .amount_out
+    }
+
+    /// Same as `swap`, also returning the fee kept by liquidity providers, charged in token_out.
+    pub fn swap_detailed(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        min_amount_out: Balance,
+        fees: &AdminFees,
+    ) -> SwapOutcome {
         assert_ne!(token_in, token_out, "{}", ERR71_SWAP_DUP_TOKENS);
         let in_idx = self.token_index(token_in);
         let out_idx = self.token_index(token_out);
@@ -500,18 +737,6 @@ impl StableSwapPool {
             "{}",
             ERR68_SLIPPAGE
         );
-        env::log(
-            format!(
-                "Swapped {} {} for {} {}, total fee {}, admin fee {}",
-                amount_in, token_in, 
-                self.c_amount_to_amount(result.amount_swapped, out_idx), 
-                token_out, 
-                self.c_amount_to_amount(result.fee, out_idx), 
-                self.c_amount_to_amount(result.admin_fee, out_idx)
-            )
-            .as_bytes(),
-        );
-
         self.c_amounts[in_idx] = result.new_source_amount;
         self.c_amounts[out_idx] = result.new_destination_amount;
         self.assert_min_reserve(self.c_amounts[out_idx]);
@@ -529,40 +754,24 @@ impl StableSwapPool {
                     fee_token = result.admin_fee * fees.referral_fee as u128
                         / (fees.referral_fee + fees.exchange_fee) as u128;
                     if fee_token > 0 {
-                        let referral_share =
-                            self.admin_fee_to_liquidity(referral, out_idx, fee_token);
-                        env::log(
-                            format!(
-                                "Referral {} got {} shares from {} {}",
-                                referral,
-                                referral_share,
-                                self.c_amount_to_amount(fee_token, out_idx),
-                                self.token_account_ids[out_idx]
-                            )
-                            .as_bytes(),
-                        );
+                        self.admin_fee_to_liquidity(referral, out_idx, fee_token);
                     }
                 }
             }
             // exchange fee = admin_fee - referral_fee
             fee_token = result.admin_fee - fee_token;
             if fee_token > 0 {
-                let exchange_share =
-                    self.admin_fee_to_liquidity(&fees.exchange_id, out_idx, fee_token);
-                env::log(
-                    format!(
-                        "Admin {} got {} shares from {} {}",
-                        &fees.exchange_id,
-                        exchange_share,
-                        self.c_amount_to_amount(fee_token, out_idx),
-                        self.token_account_ids[out_idx]
-                    )
-                    .as_bytes(),
-                );
+                self.admin_fee_to_liquidity(&fees.exchange_id, out_idx, fee_token);
             }
         }
 
-        self.c_amount_to_amount(result.amount_swapped, out_idx)
+        SwapOutcome {
+            amount_out: self.c_amount_to_amount(result.amount_swapped, out_idx),
+            fee_token: out_idx,
+            total_fee: self.c_amount_to_amount(result.fee, out_idx),
+            admin_fee: self.c_amount_to_amount(result.admin_fee, out_idx),
+            lp_fee: self.c_amount_to_amount(result.fee - result.admin_fee, out_idx),
+        }
     }
 
     /// convert admin_fee into shares without any fee.
@@ -626,6 +835,13 @@ impl StableSwapPool {
         self.shares.insert(account_id, &0);
     }
 
+    /// Removes share entry of given account, freeing its storage.
+    /// Fails if the account still has shares.
+    pub fn share_unregister(&mut self, account_id: &AccountId) {
+        let balance = self.shares.remove(account_id).expect(ERR13_LP_NOT_REGISTERED);
+        assert_eq!(balance, 0, "{}", ERR15_NON_ZERO_LP_SHARES);
+    }
+
     /// Transfers shares from predecessor to receiver.
     pub fn share_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
         let balance = self.shares.get(&sender_id).expect(ERR13_LP_NOT_REGISTERED);
@@ -646,6 +862,11 @@ impl StableSwapPool {
         self.shares.get(account_id).unwrap_or_default()
     }
 
+    /// Returns if given account has an entry in shares, even with 0 balance.
+    pub fn share_has_registered(&self, account_id: &AccountId) -> bool {
+        self.shares.contains_key(account_id)
+    }
+
     /// Returns total number of shares in this pool.
     pub fn share_total_balance(&self) -> Balance {
         self.shares_total_supply
@@ -656,6 +877,24 @@ impl StableSwapPool {
         &self.token_account_ids
     }
 
+    /// Replaces given token with a new token account, returns the pool's reserve of it.
+    /// The new token must have the same decimals, as known to the caller, or amounts would be mis-scaled,
+    /// otherwise the pool is left as it is.
+    pub fn migrate_token(
+        &mut self,
+        old_token_id: &AccountId,
+        new_token_id: &AccountId,
+        new_decimals: Option<u8>,
+    ) -> Result<Balance, ErrorCode> {
+        assert!(!self.token_account_ids.contains(new_token_id), "{}", ERR80_ILLEGAL_TOKEN_MIGRATION);
+        let index = self.token_index(old_token_id);
+        if new_decimals != Some(self.token_decimals[index]) {
+            return Err(ErrorCode::E77TokenDecimalsMismatch);
+        }
+        self.token_account_ids[index] = new_token_id.clone();
+        Ok(self.get_amounts()[index])
+    }
+
     /// [Admin function] increase the amplification factor.
     pub fn ramp_amplification(&mut self, future_amp_factor: u128, future_amp_time: Timestamp) {
         let current_time = env::block_timestamp();
@@ -960,6 +1199,32 @@ mod tests {
         assert!(shares_burned < num_shares);
     }
 
+    #[test]
+    fn test_stable_remove_liquidity_one_coin() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let mut pool =
+            StableSwapPool::new(0, vec![accounts(1), accounts(2)], vec![6, 6], 10000, 25);
+        let fees = AdminFees::new(1000); // 10% exchange fee
+        let mut amounts = vec![5000000, 10000000];
+        let _ = pool.add_liquidity(accounts(0).as_ref(), &mut amounts, 1, &fees);
+
+        // Add only one side of the capital, and withdraw on same side by shares.
+        let mut amounts2 = vec![5000000, 0];
+        let num_shares = pool.add_liquidity(accounts(1).as_ref(), &mut amounts2, 1, &fees);
+        let predicted = pool.predict_remove_liquidity_one_coin(num_shares, accounts(1).as_ref(), &fees);
+        let by_tokens = pool.predict_remove_liquidity_by_tokens(&vec![predicted, 0], &fees);
+        let prev_exchange_shares = pool.share_balance_of(&fees.exchange_id);
+        let out = pool.remove_liquidity_one_coin(accounts(1).as_ref(), num_shares, accounts(1).as_ref(), 1, &fees);
+        assert_eq!(out, predicted);
+        // Same as withdrawing that amount by tokens, up to rounding of the amount out.
+        assert!(by_tokens <= num_shares && num_shares - by_tokens < num_shares / 100000);
+        // Imbalanced withdraw is charged, part of it going to exchange.
+        assert!(out < 5000000);
+        assert!(pool.share_balance_of(&fees.exchange_id) > prev_exchange_shares);
+        assert_eq!(pool.share_balance_of(accounts(1).as_ref()), 0);
+    }
+
     /// Test everything with fees.
     #[test]
     fn test_stable_with_fees() {
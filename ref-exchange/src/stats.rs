@@ -0,0 +1,144 @@
+//! Rolling swap statistics of pools in hourly buckets, covering the last 7 days.
+//!
+//! Each pool has one slot per hour of the week, reused once the hour it holds is a week old,
+//! so storage per pool stays bounded. Statistics are opt-in: `enable_pool_stats` writes all
+//! the slots of a pool upfront, paid by the caller, and swaps only update slots that exist,
+//! so they never add storage on the contract's account.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, Balance};
+
+use crate::utils::SwapOutcome;
+use crate::*;
+
+/// Length of a bucket in seconds.
+pub const STATS_BUCKET_SEC: u64 = 3600;
+/// Number of buckets kept per pool, 7 days of hours.
+pub const STATS_NUM_BUCKETS: u64 = 168;
+
+/// Swaps in a pool during one hour, amounts in pool tokens sequence.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StatsBucket {
+    /// Hours since unix epoch.
+    pub hour: u64,
+    pub volume_in: Vec<Balance>,
+    pub volume_out: Vec<Balance>,
+    pub lp_fees: Vec<Balance>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct PoolStats {
+    pub window_hours: u32,
+    /// Amounts of each token swapped into the pool.
+    pub volume_in: Vec<U128>,
+    /// Amounts of each token swapped out of the pool.
+    pub volume_out: Vec<U128>,
+    /// Trading fees kept by liquidity providers, in the token they were charged in.
+    pub lp_fees: Vec<U128>,
+}
+
+impl StatsBucket {
+    fn new(hour: u64, num_tokens: usize) -> Self {
+        Self {
+            hour,
+            volume_in: vec![0; num_tokens],
+            volume_out: vec![0; num_tokens],
+            lp_fees: vec![0; num_tokens],
+        }
+    }
+}
+
+fn current_hour() -> u64 {
+    env::block_timestamp() / 1_000_000_000 / STATS_BUCKET_SEC
+}
+
+impl Contract {
+    /// Adds a swap to the current hour's bucket of the pool.
+    pub(crate) fn internal_record_swap_stats(
+        &mut self,
+        pool_id: u64,
+        pool: &Pool,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+        outcome: &SwapOutcome,
+    ) {
+        let hour = current_hour();
+        let key = (pool_id, (hour % STATS_NUM_BUCKETS) as u32);
+        let mut bucket = match self.pool_stats.get(&key) {
+            Some(bucket) if bucket.hour == hour => bucket,
+            Some(_) => StatsBucket::new(hour, pool.tokens().len()),
+            // Stats not enabled on the pool.
+            None => return,
+        };
+        let index = |token_id: &AccountId| {
+            pool.tokens()
+                .iter()
+                .position(|id| id == token_id)
+                .expect(ERR63_MISSING_TOKEN)
+        };
+        bucket.volume_in[index(token_in)] += amount_in;
+        bucket.volume_out[index(token_out)] += outcome.amount_out;
+        bucket.lp_fees[outcome.fee_token] += outcome.lp_fee;
+        self.pool_stats.insert(&key, &bucket);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Enables swap statistics of given pool by writing all its hourly slots.
+    /// Anyone can call it, attached NEAR should cover their storage, the rest is refunded.
+    /// Slots that already exist are kept and not charged again.
+    #[payable]
+    pub fn enable_pool_stats(&mut self, pool_id: u64) {
+        self.assert_contract_running();
+        let prev_storage = env::storage_usage();
+        let num_tokens = self.pools.get(pool_id).expect("ERR_NO_POOL").tokens().len();
+        for slot in 0..STATS_NUM_BUCKETS {
+            let key = (pool_id, slot as u32);
+            if !self.pool_stats.contains_key(&key) {
+                self.pool_stats.insert(&key, &StatsBucket::new(0, num_tokens));
+            }
+        }
+        self.internal_check_storage(prev_storage);
+    }
+
+    /// Returns whether swap statistics are recorded for given pool.
+    pub fn is_pool_stats_enabled(&self, pool_id: u64) -> bool {
+        (0..STATS_NUM_BUCKETS).all(|slot| self.pool_stats.contains_key(&(pool_id, slot as u32)))
+    }
+
+    /// Returns swap volumes and LP fees of given pool over the last `window_hours` hours,
+    /// the current hour included, e.g. 24 for a day and 168 for a week.
+    /// Swaps are only counted while stats are enabled on the pool, see `enable_pool_stats`.
+    pub fn get_pool_stats(&self, pool_id: u64, window_hours: u32) -> PoolStats {
+        assert!(
+            window_hours > 0 && window_hours as u64 <= STATS_NUM_BUCKETS,
+            "{}",
+            ERR85_ILLEGAL_STATS_WINDOW
+        );
+        let num_tokens = self.pools.get(pool_id).expect("ERR_NO_POOL").tokens().len();
+        let mut stats = PoolStats {
+            window_hours,
+            volume_in: vec![U128(0); num_tokens],
+            volume_out: vec![U128(0); num_tokens],
+            lp_fees: vec![U128(0); num_tokens],
+        };
+        let now = current_hour();
+        for hour in now.saturating_sub(window_hours as u64 - 1)..=now {
+            let key = (pool_id, (hour % STATS_NUM_BUCKETS) as u32);
+            if let Some(bucket) = self.pool_stats.get(&key).filter(|bucket| bucket.hour == hour) {
+                for i in 0..num_tokens {
+                    stats.volume_in[i].0 += bucket.volume_in[i];
+                    stats.volume_out[i].0 += bucket.volume_out[i];
+                    stats.lp_fees[i].0 += bucket.lp_fees[i];
+                }
+            }
+        }
+        stats
+    }
+}
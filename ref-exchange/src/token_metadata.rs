@@ -0,0 +1,169 @@
+//! Registry of token metadata read from the token contracts.
+//!
+//! `ft_metadata` of a token is fetched when it's whitelisted or first used in a stable pool,
+//! and can be refreshed later. Simple pools, which anyone can add, don't fetch it, so
+//! the exchange only pays the storage of tokens chosen by owner, guardians or bonded pool creators.
+//! A stable pool added before its tokens are in the registry is deactivated
+//! if the metadata read afterwards has other decimals than the pool.
+//! Only name, symbol and decimals are kept, with name and symbol cut to a bounded length.
+
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, serde_json, AccountId, PromiseResult};
+
+use crate::utils::{ext_ft_metadata, ext_self, GAS_FOR_BASIC_OP, NO_DEPOSIT};
+use crate::*;
+
+/// Max number of chars kept of token name.
+const MAX_NAME_LEN: usize = 64;
+/// Max number of chars kept of token symbol.
+const MAX_SYMBOL_LEN: usize = 16;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    /// Block timestamp in seconds the metadata was fetched.
+    pub updated_at_sec: u64,
+}
+
+impl From<&FungibleTokenMetadata> for TokenMetadata {
+    fn from(metadata: &FungibleTokenMetadata) -> Self {
+        Self {
+            name: metadata.name.chars().take(MAX_NAME_LEN).collect(),
+            symbol: metadata.symbol.chars().take(MAX_SYMBOL_LEN).collect(),
+            decimals: metadata.decimals,
+            updated_at_sec: env::block_timestamp() / 1_000_000_000,
+        }
+    }
+}
+
+impl Contract {
+    /// Fetches `ft_metadata` of given tokens, each result is recorded by its own callback.
+    pub(crate) fn internal_fetch_token_metadata<'a>(&self, token_ids: impl Iterator<Item = &'a AccountId>) {
+        for token_id in token_ids {
+            ext_ft_metadata::ft_metadata(token_id, NO_DEPOSIT, GAS_FOR_BASIC_OP).then(
+                ext_self::exchange_callback_token_metadata(
+                    token_id.clone(),
+                    &env::current_account_id(),
+                    NO_DEPOSIT,
+                    GAS_FOR_BASIC_OP,
+                ),
+            );
+        }
+    }
+
+    /// Records metadata from the result of an `ft_metadata` call, returns None if the call failed.
+    pub(crate) fn internal_record_token_metadata(
+        &mut self,
+        token_id: &AccountId,
+        result: PromiseResult,
+    ) -> Option<TokenMetadata> {
+        let metadata = match result {
+            PromiseResult::Successful(value) => serde_json::from_slice::<FungibleTokenMetadata>(&value).ok()?,
+            _ => return None,
+        };
+        let metadata = TokenMetadata::from(&metadata);
+        self.token_metadata.insert(token_id, &metadata);
+        Some(metadata)
+    }
+
+    /// Fetches metadata of the tokens of given stable pool that are not in the registry yet,
+    /// the callback deactivates the pool if their decimals differ from the pool's.
+    pub(crate) fn internal_check_missing_token_decimals(&self, pool_id: u64, token_ids: Vec<AccountId>) {
+        let token_ids: Vec<AccountId> = token_ids
+            .into_iter()
+            .filter(|token_id| !self.token_metadata.contains_key(token_id))
+            .collect();
+        if token_ids.is_empty() {
+            return;
+        }
+        let mut metadata = ext_ft_metadata::ft_metadata(&token_ids[0], NO_DEPOSIT, GAS_FOR_BASIC_OP);
+        for token_id in token_ids.iter().skip(1) {
+            metadata = metadata.and(ext_ft_metadata::ft_metadata(token_id, NO_DEPOSIT, GAS_FOR_BASIC_OP));
+        }
+        metadata.then(ext_self::exchange_callback_check_token_decimals(
+            pool_id,
+            token_ids,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_BASIC_OP,
+        ));
+    }
+
+    /// Panics if the registry knows any of the tokens with other decimals than given.
+    pub(crate) fn assert_token_decimals(&self, token_ids: &[ValidAccountId], decimals: &[u8]) {
+        for (token_id, decimals) in token_ids.iter().zip(decimals.iter()) {
+            if let Some(metadata) = self.token_metadata.get(token_id.as_ref()) {
+                assert_eq!(metadata.decimals, *decimals, "{}", ERR77_TOKEN_DECIMALS_MISMATCH);
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Records the metadata of given token, returns whether it was read.
+    #[private]
+    pub fn exchange_callback_token_metadata(&mut self, token_id: AccountId) -> bool {
+        self.internal_record_token_metadata(&token_id, env::promise_result(0))
+            .is_some()
+    }
+
+    /// Records the metadata of given tokens of a stable pool, and deactivates the pool
+    /// if any of them has other decimals than the pool. Tokens without metadata are not checked.
+    /// Returns whether the pool stays active.
+    #[private]
+    pub fn exchange_callback_check_token_decimals(&mut self, pool_id: u64, token_ids: Vec<AccountId>) -> bool {
+        let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let pool_decimals = match &pool {
+            Pool::StableSwapPool(pool) => pool.token_decimals.clone(),
+            _ => return true,
+        };
+        let pool_tokens = pool.tokens();
+        let mut matched = true;
+        for (index, token_id) in token_ids.iter().enumerate() {
+            if let Some(metadata) = self.internal_record_token_metadata(token_id, env::promise_result(index as u64)) {
+                let position = pool_tokens.iter().position(|t| t == token_id).expect(ERR63_MISSING_TOKEN);
+                matched &= metadata.decimals == pool_decimals[position];
+            }
+        }
+        if !matched {
+            self.deactivated_pools.insert(&pool_id);
+            log!("Pool {} deactivated: {}", pool_id, ERR77_TOKEN_DECIMALS_MISMATCH);
+        }
+        matched
+    }
+
+    /// Returns ids of stable pools deactivated as their decimals differ from the token metadata.
+    pub fn get_deactivated_pools(&self) -> Vec<u64> {
+        self.deactivated_pools.to_vec()
+    }
+
+    /// Fetches again the metadata of given tokens.
+    /// Anyone can refresh tokens that are whitelisted or already in the registry,
+    /// other tokens only owner or guardians.
+    pub fn refresh_token_metadata(&mut self, token_ids: Vec<ValidAccountId>) {
+        let is_owner_or_guardians = self.is_owner_or_guardians();
+        for token_id in token_ids.iter() {
+            assert!(
+                is_owner_or_guardians
+                    || self.whitelisted_tokens.contains(token_id.as_ref())
+                    || self.token_metadata.contains_key(token_id.as_ref()),
+                "{}",
+                ERR100_NOT_ALLOWED
+            );
+        }
+        self.internal_fetch_token_metadata(token_ids.iter().map(|token_id| token_id.as_ref()));
+    }
+
+    /// Returns metadata of given token, None if not fetched yet or the token has none.
+    pub fn get_token_metadata(&self, token_id: ValidAccountId) -> Option<TokenMetadata> {
+        self.token_metadata.get(token_id.as_ref())
+    }
+}
@@ -0,0 +1,308 @@
+//! Migration of a token to a new contract account, for example after a bridge upgrade.
+//!
+//! Owner proposes the migration, which fetches metadata of both tokens, and once its timelock
+//! is over verifies the tokens have the same decimals and the exchange's balance of the new token
+//! covers what it owes in both tokens. The old token is denied as soon as the verification succeeds,
+//! so no deposit of it can raise its liability past what was checked.
+//! Pools are then migrated in batches by id, and inner accounts in batches of given ids,
+//! moving the liability along. The whitelist and the fee harvest config are remapped
+//! with the first batch, and the trading delegations of each migrated account with it.
+//! MFT allowances of the old token are not remapped: they are keyed by owner and spender pairs
+//! that can't be listed, so owners approve the new token again.
+
+use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Promise, PromiseResult};
+
+use crate::utils::{ext_self, GAS_FOR_BASIC_OP, NO_DEPOSIT};
+use crate::*;
+
+/// Seconds between the proposal of a migration and its first batch.
+pub const TOKEN_MIGRATION_TIMELOCK_SEC: u64 = 2 * 24 * 3600;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct TokenMigration {
+    pub new_token_id: AccountId,
+    /// Block timestamp in seconds from which the migration can be verified.
+    pub executable_at_sec: u64,
+    /// Whether the exchange's balance of the new token covered the liabilities of both tokens,
+    /// checked after the timelock. The old token is denied from then on.
+    pub verified: bool,
+    /// Pools with smaller ids are migrated.
+    pub next_pool_id: u64,
+    /// Number of inner accounts migrated so far.
+    pub num_accounts: u64,
+}
+
+impl TokenMigration {
+    fn is_started(&self) -> bool {
+        self.next_pool_id > 0 || self.num_accounts > 0
+    }
+
+    /// Reason the old token is denied for once the migration is verified.
+    fn denied_reason(&self) -> String {
+        format!("migrated to {}", self.new_token_id)
+    }
+}
+
+fn now_sec() -> u64 {
+    env::block_timestamp() / 1_000_000_000
+}
+
+impl Contract {
+    /// Returns the migration of given token, ready to run a batch.
+    /// Remaps the whitelist and the fee harvest config before the first batch.
+    fn internal_start_token_migration(&mut self, old_token_id: &AccountId) -> TokenMigration {
+        let migration = self.token_migrations.get(old_token_id).expect(ERR78_NO_TOKEN_MIGRATION);
+        assert!(migration.verified, "{}", ERR79_TOKEN_MIGRATION_NOT_READY);
+        if !migration.is_started() {
+            let new_token_id = &migration.new_token_id;
+            if self.whitelisted_tokens.remove(old_token_id) {
+                self.whitelisted_tokens.insert(new_token_id);
+            }
+            if let Some(config) = self.fee_harvest_config.as_mut() {
+                if config.treasury_token_id.as_ref() == Some(old_token_id) {
+                    config.treasury_token_id = Some(new_token_id.clone());
+                }
+                if let Some(pool_id) = config.swap_pools.remove(old_token_id) {
+                    config.swap_pools.insert(new_token_id.clone(), pool_id);
+                }
+            }
+        }
+        migration
+    }
+
+    /// Whether the registry has metadata of both tokens with the same decimals.
+    fn is_token_decimals_matched(&self, old_token_id: &AccountId, new_token_id: &AccountId) -> bool {
+        match (self.token_metadata.get(old_token_id), self.token_metadata.get(new_token_id)) {
+            (Some(old), Some(new)) => old.decimals == new.decimals,
+            _ => false,
+        }
+    }
+
+    fn internal_move_liability(&mut self, old_token_id: &AccountId, new_token_id: &AccountId, amount: Balance) {
+        self.internal_decrease_liability(old_token_id, amount);
+        self.internal_increase_liability(new_token_id, amount);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Proposes to replace given token with a new contract account in pools and inner accounts.
+    /// Fetches metadata of both tokens, which `verify_token_migration` checks.
+    /// Batches can run after the timelock, once `verify_token_migration` succeeds.
+    /// Only can be called by owner.
+    #[payable]
+    pub fn propose_token_migration(&mut self, old_token_id: ValidAccountId, new_token_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let (old_token_id, new_token_id): (AccountId, AccountId) = (old_token_id.into(), new_token_id.into());
+        assert!(
+            old_token_id != new_token_id && self.token_migrations.get(&old_token_id).is_none(),
+            "{}",
+            ERR80_ILLEGAL_TOKEN_MIGRATION
+        );
+        if let (Some(old), Some(new)) = (
+            self.token_metadata.get(&old_token_id),
+            self.token_metadata.get(&new_token_id),
+        ) {
+            assert_eq!(old.decimals, new.decimals, "{}", ERR77_TOKEN_DECIMALS_MISMATCH);
+        }
+        self.internal_fetch_token_metadata(vec![&old_token_id, &new_token_id].into_iter());
+        let executable_at_sec = now_sec() + TOKEN_MIGRATION_TIMELOCK_SEC;
+        self.token_migrations.insert(
+            &old_token_id,
+            &TokenMigration {
+                new_token_id: new_token_id.clone(),
+                executable_at_sec,
+                verified: false,
+                next_pool_id: 0,
+                num_accounts: 0,
+            },
+        );
+        env::log(
+            format!(
+                "Token migration from {} to {} proposed, executable at {}",
+                old_token_id, new_token_id, executable_at_sec
+            )
+            .as_bytes(),
+        );
+    }
+
+    /// Cancels the migration of given token before any batch ran,
+    /// lifting the denial it put on the old token. Only can be called by owner.
+    #[payable]
+    pub fn cancel_token_migration(&mut self, old_token_id: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let migration = self.token_migrations.get(old_token_id.as_ref()).expect(ERR78_NO_TOKEN_MIGRATION);
+        assert!(!migration.is_started(), "{}", ERR80_ILLEGAL_TOKEN_MIGRATION);
+        if self.denied_tokens.get(old_token_id.as_ref()) == Some(migration.denied_reason()) {
+            self.denied_tokens.remove(old_token_id.as_ref());
+        }
+        self.token_migrations.remove(old_token_id.as_ref());
+    }
+
+    /// Checks the exchange's balance of the new token against liabilities of both tokens,
+    /// once the timelock is over, and denies the old token if it covers them
+    /// and the registry has both tokens with the same decimals.
+    /// Returns promise resolving into whether the migration is verified. Only can be called by owner.
+    pub fn verify_token_migration(&mut self, old_token_id: ValidAccountId) -> Promise {
+        self.assert_owner();
+        let migration = self.token_migrations.get(old_token_id.as_ref()).expect(ERR78_NO_TOKEN_MIGRATION);
+        assert!(now_sec() >= migration.executable_at_sec, "{}", ERR79_TOKEN_MIGRATION_NOT_READY);
+        ext_fungible_token::ft_balance_of(
+            env::current_account_id(),
+            &migration.new_token_id,
+            NO_DEPOSIT,
+            GAS_FOR_BASIC_OP,
+        )
+        .then(ext_self::exchange_callback_verify_token_migration(
+            old_token_id.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_BASIC_OP,
+        ))
+    }
+
+    #[private]
+    pub fn exchange_callback_verify_token_migration(&mut self, old_token_id: AccountId) -> bool {
+        let mut migration = self.token_migrations.get(&old_token_id).expect(ERR78_NO_TOKEN_MIGRATION);
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value)
+                    .expect(ERR52_BALANCE_UNAVAILABLE)
+                    .0
+            }
+            _ => env::panic(ERR52_BALANCE_UNAVAILABLE.as_bytes()),
+        };
+        let liability = self.token_liabilities.get(&old_token_id).unwrap_or(0)
+            + self.token_liabilities.get(&migration.new_token_id).unwrap_or(0);
+        migration.verified = self.is_liability_complete(&old_token_id)
+            && self.is_liability_complete(&migration.new_token_id)
+            && self.is_token_decimals_matched(&old_token_id, &migration.new_token_id)
+            && balance >= liability;
+        if migration.verified {
+            self.denied_tokens.insert(&old_token_id, &migration.denied_reason());
+        }
+        self.token_migrations.insert(&old_token_id, &migration);
+        migration.verified
+    }
+
+    /// Migrates the token in up to `limit` pools from the last migrated one.
+    /// Pools already holding the new token are skipped, and so are stable pools unless the registry
+    /// still has metadata of both tokens with the decimals of the pool. Skipped pools keep the old token.
+    /// Returns id of the next pool to check, the number of pools once all are done.
+    /// Only can be called by owner.
+    #[payable]
+    pub fn migrate_token_pools(&mut self, old_token_id: ValidAccountId, limit: u64) -> u64 {
+        assert_one_yocto();
+        self.assert_owner();
+        let old_token_id: AccountId = old_token_id.into();
+        let mut migration = self.internal_start_token_migration(&old_token_id);
+        let new_token_id = migration.new_token_id.clone();
+        // Stable pools scale amounts by decimals, so both tokens must be in the registry with the same.
+        let new_decimals = match (
+            self.token_metadata.get(&old_token_id),
+            self.token_metadata.get(&new_token_id),
+        ) {
+            (Some(old), Some(new)) if old.decimals == new.decimals => Some(new.decimals),
+            _ => None,
+        };
+        let end = std::cmp::min(migration.next_pool_id.saturating_add(limit), self.pools.len());
+        for pool_id in migration.next_pool_id..end {
+            let mut pool = self.pools.get(pool_id).unwrap();
+            if !pool.tokens().contains(&old_token_id) {
+                continue;
+            }
+            if pool.tokens().contains(&new_token_id) {
+                env::log(format!("Pool {} holds both tokens, skipped", pool_id).as_bytes());
+                continue;
+            }
+            let amount = match pool.migrate_token(&old_token_id, &new_token_id, new_decimals) {
+                Ok(amount) => amount,
+                Err(err) => {
+                    env::log(format!("Pool {} skipped: {}", pool_id, err.message()).as_bytes());
+                    continue;
+                }
+            };
+            self.pools.replace(pool_id, &pool);
+            self.internal_move_liability(&old_token_id, &new_token_id, amount);
+            Event::PoolTokenMigrated {
+                pool_id,
+                old_token_id: &old_token_id,
+                new_token_id: &new_token_id,
+                amount: U128(amount),
+            }
+            .emit();
+        }
+        migration.next_pool_id = std::cmp::max(end, migration.next_pool_id);
+        self.token_migrations.insert(&old_token_id, &migration);
+        migration.next_pool_id
+    }
+
+    /// Migrates the token balance, lost-found and trading delegations of given inner accounts.
+    /// Accounts without the old token are left as they are. Only can be called by owner.
+    #[payable]
+    pub fn migrate_token_accounts(&mut self, old_token_id: ValidAccountId, account_ids: Vec<ValidAccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        let old_token_id: AccountId = old_token_id.into();
+        let mut migration = self.internal_start_token_migration(&old_token_id);
+        let new_token_id = migration.new_token_id.clone();
+        for account_id in account_ids.iter().map(|account_id| account_id.as_ref()) {
+            let mut migrated = false;
+            let mut amount = 0;
+            if let Some(mut account) = self.internal_get_account(account_id) {
+                if let Some(balance) = account.migrate_token(&old_token_id, &new_token_id) {
+                    // Storage usage doesn't grow, the contract covers the longer id of the new token if any.
+                    self.accounts.insert(account_id, &account.into());
+                    migrated = true;
+                    amount += balance;
+                }
+            }
+            if let Some(mut tokens) = self.lostfound.get(account_id) {
+                if let Some(balance) = tokens.remove(&old_token_id) {
+                    *tokens.entry(new_token_id.clone()).or_insert(0) += balance;
+                    self.lostfound.insert(account_id, &tokens);
+                    migrated = true;
+                    amount += balance;
+                }
+            }
+            if let Some(mut delegations) = self.trading_delegations.get(account_id) {
+                let mut remapped = false;
+                for delegation in delegations.values_mut() {
+                    if let Some(quota) = delegation.quotas.remove(&old_token_id) {
+                        delegation.quotas.entry(new_token_id.clone()).or_insert(quota);
+                        remapped = true;
+                    }
+                }
+                if remapped {
+                    self.trading_delegations.insert(account_id, &delegations);
+                }
+            }
+            if !migrated {
+                continue;
+            }
+            self.internal_move_liability(&old_token_id, &new_token_id, amount);
+            migration.num_accounts += 1;
+            Event::AccountTokenMigrated {
+                account_id,
+                old_token_id: &old_token_id,
+                new_token_id: &new_token_id,
+                amount: U128(amount),
+            }
+            .emit();
+        }
+        self.token_migrations.insert(&old_token_id, &migration);
+    }
+
+    /// Returns the migration of given token, None if not proposed.
+    pub fn get_token_migration(&self, old_token_id: ValidAccountId) -> Option<TokenMigration> {
+        self.token_migrations.get(old_token_id.as_ref())
+    }
+}
@@ -18,6 +18,17 @@ enum TokenReceiverMessage {
         /// List of sequential actions.
         actions: Vec<Action>,
     },
+    /// Alternative to deposit + add liquidity call, adding all of the tokens received.
+    /// Stable pools take them as they are, simple pools get them through a zap, see `add_liquidity_single`.
+    /// Sender must be registered in the pool already, other storage used is paid from its storage deposit.
+    AddLiquidity {
+        pool_id: u64,
+        min_shares: U128,
+    },
+    /// Deposit into the inner account of another registered account.
+    DepositTo {
+        account_id: ValidAccountId,
+    },
 }
 
 impl Contract {
@@ -25,6 +36,7 @@ impl Contract {
     /// Returns amounts to send to the sender directly.
     fn internal_direct_actions(
         &mut self,
+        sender_id: &AccountId,
         token_in: AccountId,
         amount_in: Balance,
         referral_id: Option<AccountId>,
@@ -36,6 +48,7 @@ impl Contract {
 
         account.deposit(&token_in, amount_in);
         let _ = self.internal_execute_actions(
+            sender_id,
             &mut account,
             &referral_id,
             &actions,
@@ -59,6 +72,7 @@ impl Contract {
 impl FungibleTokenReceiver for Contract {
     /// Callback on receiving tokens by this contract.
     /// `msg` format is either "" for deposit or `TokenReceiverMessage`.
+    /// If adding liquidity or deposit to another account fails, all of the tokens are returned.
     #[allow(unreachable_code)]
     fn ft_on_transfer(
         &mut self,
@@ -68,6 +82,7 @@ impl FungibleTokenReceiver for Contract {
     ) -> PromiseOrValue<U128> {
         self.assert_contract_running();
         let token_in = env::predecessor_account_id();
+        self.assert_token_not_denied(&token_in);
         if msg.is_empty() {
             // Simple deposit.
             self.internal_deposit(sender_id.as_ref(), &token_in, amount.into());
@@ -81,8 +96,10 @@ impl FungibleTokenReceiver for Contract {
                     referral_id,
                     actions,
                 } => {
+                    self.internal_increase_liability(&token_in, amount.0);
                     let referral_id = referral_id.map(|x| x.to_string());
                     let out_amounts = self.internal_direct_actions(
+                        sender_id.as_ref(),
                         token_in,
                         amount.0,
                         referral_id,
@@ -94,6 +111,49 @@ impl FungibleTokenReceiver for Contract {
                     // Even if send tokens fails, we don't return funds back to sender.
                     PromiseOrValue::Value(U128(0))
                 }
+                TokenReceiverMessage::AddLiquidity {
+                    pool_id,
+                    min_shares,
+                } => {
+                    self.internal_deposit(sender_id.as_ref(), &token_in, amount.0);
+                    let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+                    assert!(
+                        pool.share_has_registered(sender_id.as_ref()),
+                        "{}",
+                        ERR13_LP_NOT_REGISTERED
+                    );
+                    match pool {
+                        Pool::SimplePool(_) => {
+                            self.internal_add_liquidity_single(
+                                sender_id.as_ref(),
+                                pool_id,
+                                &token_in,
+                                amount.0,
+                                min_shares.0,
+                                true,
+                            );
+                        }
+                        Pool::StableSwapPool(_) => {
+                            let amounts = pool
+                                .tokens()
+                                .iter()
+                                .map(|token| if token == &token_in { amount.0 } else { 0 })
+                                .collect();
+                            self.internal_add_stable_liquidity(
+                                sender_id.as_ref(),
+                                pool_id,
+                                amounts,
+                                min_shares.0,
+                                true,
+                            );
+                        }
+                    }
+                    PromiseOrValue::Value(U128(0))
+                }
+                TokenReceiverMessage::DepositTo { account_id } => {
+                    self.internal_deposit(account_id.as_ref(), &token_in, amount.0);
+                    PromiseOrValue::Value(U128(0))
+                }
             }
         }
     }
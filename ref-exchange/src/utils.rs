@@ -24,6 +24,9 @@ pub const GAS_FOR_FT_TRANSFER: Gas = 20_000_000_000_000;
 /// Fee divisor, allowing to provide fee in bps.
 pub const FEE_DIVISOR: u32 = 10_000;
 
+/// Scale of token prices returned by views.
+pub const PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
+
 /// Initial shares supply on deposit of liquidity.
 pub const INIT_SHARES_SUPPLY: u128 = 1_000_000_000_000_000_000_000_000;
 
@@ -49,6 +52,19 @@ impl Default for SwapVolume {
     }
 }
 
+/// Amount received from a swap in a pool, and the fee kept by liquidity providers.
+pub struct SwapOutcome {
+    pub amount_out: Balance,
+    /// Index of the pool token the fee was charged in.
+    pub fee_token: usize,
+    /// Whole trading fee.
+    pub total_fee: Balance,
+    /// Exchange and referral fees taken out of the trading fee.
+    pub admin_fee: Balance,
+    /// Trading fee left after exchange and referral fees.
+    pub lp_fee: Balance,
+}
+
 #[ext_contract(ext_self)]
 pub trait RefExchange {
     fn exchange_callback_post_withdraw(
@@ -57,6 +73,37 @@ pub trait RefExchange {
         sender_id: AccountId,
         amount: U128,
     );
+
+    fn exchange_callback_post_withdraw_call(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    );
+
+    fn exchange_callback_solvency_report(&self, token_id: AccountId);
+
+    fn exchange_callback_retrieve_unmanaged_token(&mut self, token_id: AccountId, amount: U128);
+
+    fn exchange_callback_post_withdraw_batch(
+        &mut self,
+        token_ids: Vec<AccountId>,
+        sender_id: AccountId,
+        amounts: Vec<U128>,
+    );
+
+    fn exchange_callback_check_pool_decimals(&mut self, pool_id: u64, decimals: Vec<u8>);
+
+    fn exchange_callback_token_metadata(&mut self, token_id: AccountId);
+
+    fn exchange_callback_check_token_decimals(&mut self, pool_id: u64, token_ids: Vec<AccountId>);
+
+    fn exchange_callback_verify_token_migration(&mut self, old_token_id: AccountId);
+}
+
+#[ext_contract(ext_ft_metadata)]
+pub trait FungibleTokenMetadataProvider {
+    fn ft_metadata(&self) -> near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 }
 
 /// Adds given value to item stored in the given key in the LookupMap collection.
@@ -106,6 +106,16 @@ impl From<Pool> for StablePoolInfo {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+pub struct AddLiquidityPrediction {
+    /// Shares minted.
+    pub shares: U128,
+    /// Amounts of each token given back, in pool tokens sequence.
+    pub refunds: Vec<U128>,
+}
+
 #[near_bindgen]
 impl Contract {
 
@@ -144,6 +154,44 @@ impl Contract {
             .collect()
     }
 
+    /// Returns tokens kept in lost-found for given account.
+    pub fn get_user_lostfound(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
+        self.lostfound
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(token_id, amount)| (token_id, U128(amount)))
+            .collect()
+    }
+
+    /// Returns number of accounts having tokens in lost-found.
+    pub fn get_number_of_lostfound_accounts(&self) -> u64 {
+        self.lostfound.len()
+    }
+
+    /// Returns lost-found of accounts of given length from given start index.
+    pub fn get_lostfound_list(
+        &self,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<(AccountId, HashMap<AccountId, U128>)> {
+        let keys = self.lostfound.keys_as_vector();
+        let values = self.lostfound.values_as_vector();
+        (from_index..std::cmp::min(from_index + limit, keys.len()))
+            .map(|index| {
+                (
+                    keys.get(index).unwrap(),
+                    values
+                        .get(index)
+                        .unwrap()
+                        .into_iter()
+                        .map(|(token_id, amount)| (token_id, U128(amount)))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
     /// Returns information about specified pool.
     pub fn get_pool(&self, pool_id: u64) -> PoolInfo {
         self.pools.get(pool_id).expect("ERR_NO_POOL").into()
@@ -164,8 +212,15 @@ impl Contract {
         self.pools.get(pool_id).expect("ERR_NO_POOL").get_volumes()
     }
 
-    pub fn get_pool_share_price(&self, pool_id: u64) -> U128 {
-        self.pools.get(pool_id).expect("ERR_NO_POOL").get_share_price().into()
+    /// Returns value of a share with 1e8 precision. Simple pools value it in given token,
+    /// the first one of the pool by default, at the pool's own spot price,
+    /// stable pools in their comparable unit.
+    pub fn get_pool_share_price(&self, pool_id: u64, token_id: Option<ValidAccountId>) -> U128 {
+        self.pools
+            .get(pool_id)
+            .expect("ERR_NO_POOL")
+            .get_share_price(token_id.as_ref().map(|token_id| token_id.as_ref()))
+            .into()
     }
 
     /// Returns number of shares given account has in given pool.
@@ -224,6 +279,11 @@ impl Contract {
         self.whitelisted_tokens.to_vec()
     }
 
+    /// Get denied tokens with the reason.
+    pub fn get_denied_tokens(&self) -> HashMap<AccountId, String> {
+        self.denied_tokens.iter().collect()
+    }
+
     /// Get specific user whitelisted tokens.
     pub fn get_user_whitelisted_tokens(&self, account_id: ValidAccountId) -> Vec<AccountId> {
         self.internal_get_account(account_id.as_ref())
@@ -256,6 +316,24 @@ impl Contract {
             .into()
     }
 
+    /// Returns number of shares adding given amounts of liquidity would mint,
+    /// and amounts refunded for not being in pool's ratio.
+    pub fn predict_add_liquidity(
+        &self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+    ) -> AddLiquidityPrediction {
+        let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        let (shares, refunds) = pool.predict_add_liquidity(
+            &amounts.into_iter().map(|x| x.0).collect(),
+            &AdminFees::new(self.exchange_fee),
+        );
+        AddLiquidityPrediction {
+            shares: U128(shares),
+            refunds: refunds.into_iter().map(U128).collect(),
+        }
+    }
+
     pub fn predict_remove_liquidity(
         &self,
         pool_id: u64,
@@ -274,4 +352,71 @@ impl Contract {
         pool.predict_remove_liquidity_by_tokens(&amounts.into_iter().map(|x| x.0).collect(), &AdminFees::new(self.exchange_fee))
             .into()
     }
+
+    /// Returns amount of given token one would get from burning given shares of a stable pool
+    /// with `remove_liquidity_one_coin`.
+    pub fn predict_remove_liquidity_one_coin(
+        &self,
+        pool_id: u64,
+        shares: U128,
+        token_id: ValidAccountId,
+    ) -> U128 {
+        let pool = self.pools.get(pool_id).expect("ERR_NO_POOL");
+        pool.predict_remove_liquidity_one_coin(shares.into(), token_id.as_ref(), &AdminFees::new(self.exchange_fee))
+            .into()
+    }
+
+    /// Same as `get_return`, returning the error code instead of failing,
+    /// so routers can skip pools that can't quote given swap.
+    pub fn try_get_return(
+        &self,
+        pool_id: u64,
+        token_in: ValidAccountId,
+        amount_in: U128,
+        token_out: ValidAccountId,
+    ) -> Result<U128, ErrorCode> {
+        let pool = self.pools.get(pool_id).ok_or(ErrorCode::E86PoolNotFound)?;
+        pool.try_get_return(token_in.as_ref(), amount_in.into(), token_out.as_ref(), &AdminFees::new(self.exchange_fee))
+            .map(U128)
+    }
+
+    pub fn try_get_pool_share_price(
+        &self,
+        pool_id: u64,
+        token_id: Option<ValidAccountId>,
+    ) -> Result<U128, ErrorCode> {
+        let pool = self.pools.get(pool_id).ok_or(ErrorCode::E86PoolNotFound)?;
+        pool.try_get_share_price(token_id.as_ref().map(|token_id| token_id.as_ref()))
+            .map(U128)
+    }
+
+    pub fn try_predict_add_stable_liquidity(
+        &self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+    ) -> Result<U128, ErrorCode> {
+        let pool = self.pools.get(pool_id).ok_or(ErrorCode::E86PoolNotFound)?;
+        pool.try_predict_add_stable_liquidity(&amounts.into_iter().map(|x| x.0).collect::<Vec<_>>(), &AdminFees::new(self.exchange_fee))
+            .map(U128)
+    }
+
+    pub fn try_predict_remove_liquidity(
+        &self,
+        pool_id: u64,
+        shares: U128,
+    ) -> Result<Vec<U128>, ErrorCode> {
+        let pool = self.pools.get(pool_id).ok_or(ErrorCode::E86PoolNotFound)?;
+        pool.try_predict_remove_liquidity(shares.into())
+            .map(|amounts| amounts.into_iter().map(U128).collect())
+    }
+
+    pub fn try_predict_remove_liquidity_by_tokens(
+        &self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+    ) -> Result<U128, ErrorCode> {
+        let pool = self.pools.get(pool_id).ok_or(ErrorCode::E86PoolNotFound)?;
+        pool.try_predict_remove_liquidity_by_tokens(&amounts.into_iter().map(|x| x.0).collect::<Vec<_>>(), &AdminFees::new(self.exchange_fee))
+            .map(U128)
+    }
 }
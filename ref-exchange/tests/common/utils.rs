@@ -147,6 +147,13 @@ pub fn get_deposits(
     view!(pool.get_deposits(account_id)).unwrap_json::<HashMap<String, U128>>()
 }
 
+pub fn get_user_lostfound(
+    pool: &ContractAccount<Exchange>, 
+    account_id: ValidAccountId
+) -> HashMap<String, U128> {
+    view!(pool.get_user_lostfound(account_id)).unwrap_json::<HashMap<String, U128>>()
+}
+
 /// get ref-exchange's whitelisted tokens
 pub fn get_whitelist(pool: &ContractAccount<Exchange>) -> Vec<String> {
     view!(pool.get_whitelisted_tokens()).unwrap_json::<Vec<String>>()
@@ -207,7 +214,7 @@ pub fn pool_share_price(
     pool: &ContractAccount<Exchange>,
     pool_id: u64,
 ) -> u128 {
-    view!(pool.get_pool_share_price(pool_id))
+    view!(pool.get_pool_share_price(pool_id, None))
         .unwrap_json::<U128>()
         .0
 }
@@ -490,7 +490,7 @@ pub fn pool_share_price(
     pool: &ContractAccount<Exchange>,
     pool_id: u64,
 ) -> u128 {
-    view!(pool.get_pool_share_price(pool_id))
+    view!(pool.get_pool_share_price(pool_id, None))
         .unwrap_json::<U128>()
         .0
 }
\ No newline at end of file
@@ -194,7 +194,7 @@ fn guardians_scenario_01() {
     // withdraw token would fail
     let out_come = call!(
         root,
-        pool.withdraw(to_va(eth()), U128(to_yocto("1")), None),
+        pool.withdraw(to_va(eth()), U128(to_yocto("1")), None, None, None),
         deposit = 1
     );
     assert!(!out_come.is_ok());
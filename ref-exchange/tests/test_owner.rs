@@ -20,6 +20,16 @@ fn owner_scenario_01() {
 
     assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("115"));
 
+    // tokens sent without deposit are not managed by contract.
+    call!(
+        root,
+        token1.ft_transfer(pool.valid_account_id(), to_yocto("10").into(), None),
+        deposit = 1
+    )
+    .assert_success();
+
+    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("125"));
+
     println!("Owner Case 0101: only owner can retrieve unmanaged tokens");
     let out_come = call!(
         root,
@@ -32,6 +42,16 @@ fn owner_scenario_01() {
     // println!("{}", get_error_status(&out_come));
 
     println!("Owner Case 0102: owner retrieve unmanaged token but unregstered");
+    // first call only requests the retrieval.
+    call!(
+        owner,
+        pool.retrieve_unmanaged_token(token1.valid_account_id(), to_yocto("10").into()),
+        deposit = 1
+    )
+    .assert_success();
+    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("125"));
+    // past the retrieval delay of 60 seconds, blocks are 1 second apart.
+    root.borrow_runtime_mut().produce_blocks(60).unwrap();
     let out_come = call!(
         owner,
         pool.retrieve_unmanaged_token(token1.valid_account_id(), to_yocto("10").into()),
@@ -40,16 +60,36 @@ fn owner_scenario_01() {
     assert!(!out_come.is_ok());
     assert_eq!(get_error_count(&out_come), 1);
     assert!(get_error_status(&out_come).contains("The account owner is not registered"));
-    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("115"));
+    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("125"));
 
-    
-    println!("Owner Case 0103: owner retrieve unmanaged tokens");
+    println!("Owner Case 0103: owner can't retrieve more than unmanaged tokens");
     call!(
         owner,
         token1.storage_deposit(None, None),
         deposit = to_yocto("1")
     )
     .assert_success();
+    let out_come = call!(
+        owner,
+        pool.retrieve_unmanaged_token(token1.valid_account_id(), to_yocto("11").into()),
+        deposit = 1
+    );
+    assert!(!out_come.is_ok());
+    assert_eq!(get_error_count(&out_come), 1);
+    assert!(get_error_status(&out_come).contains("E55: amount exceeds token surplus"));
+    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("125"));
+
+    
+    println!("Owner Case 0104: owner retrieve unmanaged tokens");
+    call!(
+        owner,
+        pool.retrieve_unmanaged_token(token1.valid_account_id(), to_yocto("10").into()),
+        deposit = 1
+    )
+    .assert_success();
+    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("125"));
+    // past the retrieval delay of 60 seconds, blocks are 1 second apart.
+    root.borrow_runtime_mut().produce_blocks(60).unwrap();
     let out_come = call!(
         owner,
         pool.retrieve_unmanaged_token(token1.valid_account_id(), to_yocto("10").into()),
@@ -57,6 +97,6 @@ fn owner_scenario_01() {
     );
     out_come.assert_success();
     assert_eq!(get_error_count(&out_come), 0);
-    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("105"));
+    assert_eq!(balance_of(&token1, &pool.account_id()), to_yocto("115"));
     assert_eq!(balance_of(&token1, &owner.account_id()), to_yocto("10"));
 }
\ No newline at end of file
@@ -212,12 +212,12 @@ fn test_swap() {
 
     call!(
         root,
-        pool.withdraw(to_va(eth()), U128(to_yocto("101")), None),
+        pool.withdraw(to_va(eth()), U128(to_yocto("101")), None, None, None),
         deposit = 1
     );
     call!(
         root,
-        pool.withdraw(to_va(dai()), U128(to_yocto("99")), None),
+        pool.withdraw(to_va(dai()), U128(to_yocto("99")), None, None, None),
         deposit = 1
     );
 
@@ -307,7 +307,7 @@ fn test_withdraw_failure() {
     // Root tries to withdraw and the transfer fails
     let withdrawal_result = call!(
         root,
-        pool.withdraw(to_va(dai()), to_yocto("30").into(), None),
+        pool.withdraw(to_va(dai()), to_yocto("30").into(), None, None, None),
         deposit = 1
     );
 